@@ -0,0 +1,114 @@
+//! dudect-style timing test for the OT receiver.
+//!
+//! Each measurement runs one receiver step with either a fixed choice (class
+//! 0) or a random one (class 1), the classes interleaved at random. Welch's
+//! t-test then compares the two timing distributions, over all measurements
+//! and again with the slowest ones cropped off as dudect does. A |t| above
+//! 4.5 means the step very likely leaks the choice.
+//!
+//!     cargo bench --bench dudect [-- SAMPLES]
+use semi_honest_ecdsa::ot::{receiver, sender, Msg};
+use std::time::Instant;
+
+/// THRESHOLD is dudect's bound on |t| for a likely leak.
+const THRESHOLD: f64 = 4.5;
+
+/// FIXED is the class 0 choice.
+const FIXED: u8 = 0;
+
+/// Welch keeps running means and variances for the two classes.
+#[derive(Default)]
+struct Welch {
+    n: [f64; 2],
+    mean: [f64; 2],
+    m2: [f64; 2],
+}
+
+impl Welch {
+    fn push(&mut self, class: usize, x: f64) {
+        self.n[class] += 1.0;
+        let delta = x - self.mean[class];
+        self.mean[class] += delta / self.n[class];
+        self.m2[class] += delta * (x - self.mean[class]);
+    }
+
+    fn t(&self) -> f64 {
+        let var = |c: usize| self.m2[c] / (self.n[c] - 1.0);
+        (self.mean[0] - self.mean[1]) / (var(0) / self.n[0] + var(1) / self.n[1]).sqrt()
+    }
+}
+
+/// max_t is the largest |t| over all measurements and over those below a
+/// few percentiles of the timings.
+fn max_t(measurements: &[(usize, f64)]) -> f64 {
+    let mut sorted: Vec<f64> = measurements.iter().map(|m| m.1).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    [1.0, 0.9, 0.75, 0.5]
+        .iter()
+        .map(|p| {
+            let crop = sorted[((sorted.len() - 1) as f64 * p) as usize];
+            let mut welch = Welch::default();
+            for &(class, x) in measurements.iter().filter(|m| m.1 <= crop) {
+                welch.push(class, x);
+            }
+            welch.t().abs()
+        })
+        .fold(0.0, f64::max)
+}
+
+/// choose picks a class at random, and the choice to measure for it.
+fn choose() -> (usize, u8) {
+    if rand::random() {
+        (0, FIXED)
+    } else {
+        (1, rand::random())
+    }
+}
+
+fn main() {
+    let samples: usize = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(10_000);
+    let ctx = secp256k1::Secp256k1::new();
+    // a pool of sender messages, shared by both classes
+    let ss: Vec<Msg> = (0..64)
+        .map(|_| sender::protocol::start::new().step(&ctx).unwrap().1.remove(0))
+        .collect();
+    let es = Msg::Ciphertexts((0..256 * 32).map(|_| rand::random()).collect());
+    let decrypt = |k: &[u8], e: &[u8]| -> [u8; 32] {
+        let mut m = [0u8; 32];
+        for (w, (a, b)) in m.iter_mut().zip(k.iter().zip(e.iter())) {
+            *w = a ^ b;
+        }
+        m
+    };
+
+    let mut point = Vec::with_capacity(samples);
+    let mut select = Vec::with_capacity(samples);
+    for i in 0..samples {
+        let (class, choice) = choose();
+        let started = receiver::protocol::start::new(choice);
+        let s = ss[i % ss.len()].clone();
+        let at = Instant::now();
+        let (sent_r, _) = started.step(&ctx, s).unwrap();
+        point.push((class, at.elapsed().as_nanos() as f64));
+
+        let es = es.clone();
+        let at = Instant::now();
+        let m = sent_r.step(es, decrypt).unwrap();
+        select.push((class, at.elapsed().as_nanos() as f64));
+        std::hint::black_box(m);
+    }
+
+    let mut leaks = false;
+    for (name, measurements) in [("point", &point), ("select", &select)].iter() {
+        let t = max_t(&measurements[..]);
+        println!("{}: max |t| = {:.2} over {} samples", name, t, samples);
+        leaks |= t > THRESHOLD;
+    }
+    if leaks {
+        println!("timing depends on the choice");
+        std::process::exit(1);
+    }
+}
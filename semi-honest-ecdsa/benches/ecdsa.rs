@@ -0,0 +1,23 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use semi_honest_ecdsa::ecdsa::{background_inverse_service, twopc};
+use semi_honest_ecdsa::Scalar;
+use std::os::unix::net::UnixStream;
+
+/// twopc_sign times a two party keygen and signature, with nonce inverses
+/// computed ahead of time as a node would.
+fn twopc_sign(c: &mut Criterion) {
+    let inverses = background_inverse_service(100);
+    c.bench_function("2pc keygen and sign", |b| {
+        b.iter(|| {
+            let (inv1, inv2) = (inverses.recv().unwrap(), inverses.recv().unwrap());
+            let (a, b) = UnixStream::pair().unwrap();
+            let m = Scalar::random();
+            let peer = std::thread::spawn(move || twopc::run(|| inv1, &m, a));
+            twopc::run(|| inv2, &m, b).unwrap();
+            peer.join().unwrap().unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, twopc_sign);
+criterion_main!(benches);
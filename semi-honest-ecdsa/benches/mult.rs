@@ -0,0 +1,85 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+use semi_honest_ecdsa::ot::extension::{ReceiverPool, SenderPool};
+use semi_honest_ecdsa::wire::Framed;
+use semi_honest_ecdsa::{mult, Result, Scalar};
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+/// bench_mult times one multiplication, from the sender's side, against a
+/// receiver on its own thread.
+fn bench_mult<S, R>(c: &mut Criterion, name: &str, sender: S, receiver: R)
+where
+    S: Fn(&Scalar, Framed<UnixStream>) -> (Scalar, thread::JoinHandle<Result<()>>),
+    R: Fn(&Scalar, Framed<UnixStream>) -> thread::JoinHandle<Result<Scalar>>,
+    R: Copy + Send + 'static,
+{
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            let (sock1, sock2) = UnixStream::pair().unwrap();
+            let r =
+                thread::spawn(move || receiver(&Scalar::random(), Framed::new(sock2, 0)).join());
+            let (_share, th) = sender(&Scalar::random(), Framed::new(sock1, 0));
+            th.join().unwrap().unwrap();
+            r.join().unwrap().unwrap().unwrap();
+        })
+    });
+}
+
+fn scaled_mult(c: &mut Criterion) {
+    bench_mult(c, "scaled mult", mult::sender::run, mult::receiver::run);
+}
+
+/// scale_free_mult times scale free multiplications at a few radixes, from
+/// 256 OTs of 2 entries to 32 of 256.
+fn scale_free_mult(c: &mut Criterion) {
+    for bits in [1, 4, 8].iter() {
+        let radix = mult::Radix::new(*bits).unwrap();
+        bench_mult(
+            c,
+            &format!("scale free mult, {} bit radix", bits),
+            move |a, p| mult::sender::run_scale_free(a, radix, p),
+            move |b, p| mult::receiver::run_scale_free(b, radix, p),
+        );
+    }
+}
+
+/// extended_mult times a scale free multiplication drawing its OTs from an
+/// extension pool set up beforehand, against a receiver which keeps
+/// multiplying until the sender hangs up.
+fn extended_mult(c: &mut Criterion) {
+    let radix = mult::Radix::default();
+    let (sock1, sock2) = UnixStream::pair().unwrap();
+    let r = thread::spawn(move || {
+        let ctx = &secp256k1::Secp256k1::new();
+        let mut peer = Framed::new(sock2, 0);
+        let mut pool = ReceiverPool::setup(ctx, &mut peer).unwrap();
+        while block_on(mult::receiver::run_scale_free_extended_async(
+            &Scalar::random(),
+            radix,
+            &mut pool,
+            &mut peer,
+        ))
+        .is_ok()
+        {}
+    });
+    let ctx = &secp256k1::Secp256k1::new();
+    let mut peer = Framed::new(sock1, 0);
+    let mut pool = SenderPool::setup(ctx, &mut peer).unwrap();
+    c.bench_function("extended scale free mult", |b| {
+        b.iter(|| {
+            block_on(mult::sender::run_scale_free_extended_async(
+                &Scalar::random(),
+                radix,
+                &mut pool,
+                &mut peer,
+            ))
+            .unwrap()
+        })
+    });
+    drop(peer);
+    r.join().unwrap();
+}
+
+criterion_group!(benches, scaled_mult, scale_free_mult, extended_mult);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use semi_honest_ecdsa::ot;
+use semi_honest_ecdsa::wire::Framed;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::thread;
+
+fn enc(m: &[u8; 32], key: &[u8], w: &mut dyn Write) -> std::io::Result<()> {
+    let e: Vec<u8> = m.iter().zip(key.iter()).map(|(a, b)| a ^ b).collect();
+    w.write_all(&e[..])
+}
+
+fn dec(key: &[u8], e: &[u8]) -> Vec<u8> {
+    key.iter().zip(e.iter()).map(|(a, b)| a ^ b).collect()
+}
+
+/// one_of_256 times a 1-of-256 OT of 32 byte messages, done directly and
+/// from 1-of-2 OTs, to choose between them per deployment.
+fn one_of_256(c: &mut Criterion) {
+    let msg: Vec<[u8; 32]> = (0..256).map(|_| rand::random()).collect();
+    let mut bench = |name: &str, one_of_n: bool| {
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                let (sock1, sock2) = UnixStream::pair().unwrap();
+                let r = thread::spawn(move || {
+                    let ctx = &secp256k1::Secp256k1::new();
+                    let peer = &mut Framed::new(sock2, 0);
+                    let choice: u8 = rand::random();
+                    if one_of_n {
+                        ot::one_of_n::receive(ctx, choice as usize, 256, dec, peer)
+                    } else {
+                        ot::receiver::run(ctx, choice, 256, dec, peer)
+                    }
+                });
+                let ctx = &secp256k1::Secp256k1::new();
+                let peer = &mut Framed::new(sock1, 0);
+                if one_of_n {
+                    ot::one_of_n::send(ctx, &msg[..], enc, peer).unwrap();
+                } else {
+                    ot::sender::run(ctx, &msg[..], enc, peer).unwrap();
+                }
+                r.join().unwrap().unwrap();
+            })
+        });
+    };
+    bench("1-of-256 ot", false);
+    bench("1-of-256 ot from 1-of-2", true);
+}
+
+criterion_group!(benches, one_of_256);
+criterion_main!(benches);
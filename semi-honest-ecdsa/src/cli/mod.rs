@@ -0,0 +1,215 @@
+use semi_honest_ecdsa::node::{self, Reply};
+use semi_honest_ecdsa::{from_hex, to_hex, Error, Result};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+#[cfg(test)]
+mod tests;
+
+/// DEFAULT_CONTROL is the control socket used when no --control is given.
+pub const DEFAULT_CONTROL: &str = "semi-honest-ecdsa.sock";
+
+pub const USAGE: &str = "usage:
+    semi-honest-ecdsa daemon --peer listen:ADDR|connect:ADDR [--control PATH]
+    semi-honest-ecdsa keygen [--control PATH] [--json]
+    semi-honest-ecdsa nonce [--control PATH] [--json]
+    semi-honest-ecdsa sign --key ID --msg HEX [--nonce ID] [--control PATH] [--json]
+    semi-honest-ecdsa approve --key ID --msg HEX [--nonce ID] [--control PATH] [--json]
+    semi-honest-ecdsa verify --pubkey HEX --msg HEX --sig HEX [--json]
+    semi-honest-ecdsa peers [--control PATH] [--json]";
+
+/// Invocation is a parsed command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Invocation {
+    /// run a node, serving the other subcommands on its control socket
+    Daemon(node::Config),
+    /// send a command line to the daemon at control
+    Control {
+        control: PathBuf,
+        command: String,
+        json: bool,
+    },
+    /// check a signature locally
+    Verify {
+        pubkey: Vec<u8>,
+        msg: Vec<u8>,
+        sig: Vec<u8>,
+        json: bool,
+    },
+}
+
+/// Options collects `--name value` and `--flag` arguments.
+struct Options(Vec<(String, Option<String>)>);
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I, flags: &[&str]) -> Result<Options> {
+        let mut options = vec![];
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                return Err(Error::Config(format!("{}: unexpected argument", arg)));
+            }
+            let value = if flags.contains(&&arg[..]) {
+                None
+            } else {
+                Some(
+                    args.next()
+                        .ok_or_else(|| Error::Config(format!("{} needs a value", arg)))?,
+                )
+            };
+            options.push((arg, value));
+        }
+        Ok(Options(options))
+    }
+
+    /// take removes option name, returning its value.
+    fn take(&mut self, name: &str) -> Option<String> {
+        let at = self.0.iter().position(|(n, _)| n == name)?;
+        self.0.remove(at).1
+    }
+
+    fn require(&mut self, name: &str) -> Result<String> {
+        self.take(name)
+            .ok_or_else(|| Error::Config(format!("{} is required", name)))
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        match self.0.iter().position(|(n, _)| n == name) {
+            Some(at) => {
+                self.0.remove(at);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn control(&mut self) -> PathBuf {
+        PathBuf::from(
+            self.take("--control")
+                .unwrap_or_else(|| DEFAULT_CONTROL.to_string()),
+        )
+    }
+
+    /// done fails if any option was not taken.
+    fn done(self) -> Result<()> {
+        match self.0.first() {
+            Some((name, _)) => Err(Error::Config(format!("{}: unknown option", name))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Invocation {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Invocation> {
+        let subcommand = args.next().ok_or_else(|| Error::Config(USAGE.to_string()))?;
+        let mut options = Options::parse(args, &["--json"])?;
+        let json = options.flag("--json");
+        let invocation = match &subcommand[..] {
+            "daemon" => Invocation::Daemon(node::Config {
+                peer: options.require("--peer")?.parse()?,
+                control: options.control(),
+            }),
+            "keygen" | "nonce" | "peers" => Invocation::Control {
+                control: options.control(),
+                command: subcommand.clone(),
+                json,
+            },
+            // the peer can only start a session we sign in once we approve it
+            "sign" | "approve" => {
+                let key = options.require("--key")?;
+                let msg = options.require("--msg")?;
+                let command = match options.take("--nonce") {
+                    Some(nonce) => format!("{} {} {} {}", subcommand, key, msg, nonce),
+                    None => format!("{} {} {}", subcommand, key, msg),
+                };
+                Invocation::Control {
+                    control: options.control(),
+                    command,
+                    json,
+                }
+            }
+            "verify" => Invocation::Verify {
+                pubkey: from_hex(&options.require("--pubkey")?)?,
+                msg: from_hex(&options.require("--msg")?)?,
+                sig: from_hex(&options.require("--sig")?)?,
+                json,
+            },
+            _ => {
+                return Err(Error::Config(format!(
+                    "{}: unknown subcommand\n{}",
+                    subcommand, USAGE
+                )))
+            }
+        };
+        options.done()?;
+        Ok(invocation)
+    }
+}
+
+/// run carries out a command line, writing its result to out.
+pub fn run<I: Iterator<Item = String>>(args: I, out: &mut dyn Write) -> Result<()> {
+    match Invocation::from_args(args)? {
+        Invocation::Daemon(config) => {
+            let node = node::Node::establish(&config.peer)?;
+            node::serve_control(&node, &config.control)
+        }
+        Invocation::Control {
+            control,
+            command,
+            json,
+        } => {
+            let reply = control_request(&UnixStream::connect(&control)?, &command)?;
+            if json {
+                writeln!(out, "{}", reply.to_json())?;
+            } else {
+                writeln!(out, "{}", reply)?;
+            }
+            Ok(())
+        }
+        Invocation::Verify {
+            pubkey,
+            msg,
+            sig,
+            json,
+        } => {
+            let valid = verify(&pubkey[..], &msg[..], &sig[..])?;
+            if json {
+                writeln!(out, "{{\"valid\":{}}}", valid)?;
+            } else {
+                writeln!(out, "{}", if valid { "valid" } else { "invalid" })?;
+            }
+            if valid {
+                Ok(())
+            } else {
+                Err(Error::VerificationFailed)
+            }
+        }
+    }
+}
+
+/// control_request sends one command to a daemon and reads its reply.
+pub fn control_request(control: &UnixStream, command: &str) -> Result<Reply> {
+    let mut writer = control;
+    writeln!(writer, "{}", command)?;
+    let mut line = String::new();
+    BufReader::new(control).read_line(&mut line)?;
+    let line = line.trim_end();
+    if let Some(reply) = line.strip_prefix("ok ") {
+        reply.parse()
+    } else if let Some(why) = line.strip_prefix("error ") {
+        Err(Error::Config(format!("daemon: {}", why)))
+    } else {
+        Err(Error::ProtocolAbort("daemon hung up"))
+    }
+}
+
+/// verify checks a compact signature over a 32 byte message exactly as
+/// given, so a high S signature, which Bitcoin rejects, is invalid. It fails
+/// only if the inputs are malformed.
+pub fn verify(pubkey: &[u8], msg: &[u8], sig: &[u8]) -> Result<bool> {
+    let ctx = secp256k1::Secp256k1::verification_only();
+    let pubkey = secp256k1::PublicKey::from_slice(pubkey).map_err(|_| Error::InvalidPoint)?;
+    let msg = secp256k1::Message::from_digest_slice(msg)
+        .map_err(|_| Error::Config(format!("{}: expected a 32 byte message", to_hex(msg))))?;
+    let sig = secp256k1::ecdsa::Signature::from_compact(sig)?;
+    Ok(ctx.verify_ecdsa(&msg, &sig, &pubkey).is_ok())
+}
@@ -0,0 +1,101 @@
+use super::*;
+
+fn args(line: &str) -> impl Iterator<Item = String> {
+    line.split_whitespace()
+        .map(String::from)
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+#[test]
+fn parse_subcommands() {
+    assert_eq!(
+        Invocation::from_args(args("daemon --peer listen:127.0.0.1:7000")).unwrap(),
+        Invocation::Daemon(node::Config {
+            peer: "listen:127.0.0.1:7000".parse().unwrap(),
+            control: PathBuf::from(DEFAULT_CONTROL),
+        })
+    );
+    assert_eq!(
+        Invocation::from_args(args("sign --json --msg ab --key 3 --nonce 5 --control c.sock"))
+            .unwrap(),
+        Invocation::Control {
+            control: PathBuf::from("c.sock"),
+            command: "sign 3 ab 5".to_string(),
+            json: true,
+        }
+    );
+    assert_eq!(
+        Invocation::from_args(args("approve --key 3 --msg ab")).unwrap(),
+        Invocation::Control {
+            control: PathBuf::from(DEFAULT_CONTROL),
+            command: "approve 3 ab".to_string(),
+            json: false,
+        }
+    );
+    assert!(Invocation::from_args(args("sign --msg ab")).is_err());
+    assert!(Invocation::from_args(args("keygen --bogus 1")).is_err());
+    assert!(Invocation::from_args(args("launch")).is_err());
+    assert!(Invocation::from_args(args("")).is_err());
+}
+
+#[test]
+fn verify_signatures() {
+    let ctx = secp256k1::Secp256k1::new();
+    let key = secp256k1::SecretKey::from_slice(&[1u8; 32]).unwrap();
+    let pubkey = secp256k1::PublicKey::from_secret_key(&ctx, &key).serialize();
+    let msg = [7u8; 32];
+    let sig = ctx
+        .sign_ecdsa(&secp256k1::Message::from_digest_slice(&msg[..]).unwrap(), &key)
+        .serialize_compact();
+    let line = format!(
+        "verify --json --pubkey {} --msg {} --sig {}",
+        to_hex(&pubkey[..]),
+        to_hex(&msg[..]),
+        to_hex(&sig[..])
+    );
+    let mut out = vec![];
+    run(args(&line), &mut out).unwrap();
+    assert_eq!(out, b"{\"valid\":true}\n");
+
+    let other = [8u8; 32];
+    assert!(!verify(&pubkey[..], &other[..], &sig[..]).unwrap());
+    assert!(verify(&pubkey[..], &other[..1], &sig[..]).is_err());
+
+    // the same signature with S negated is high S
+    let mut s = [0u8; 32];
+    s.clone_from_slice(&sig[32..]);
+    let mut high = sig;
+    high[32..].clone_from_slice(&(-semi_honest_ecdsa::Scalar::from_bytes(&s)).to_bytes()[..]);
+    assert!(!verify(&pubkey[..], &msg[..], &high[..]).unwrap());
+}
+
+#[test]
+fn talk_to_daemon() {
+    let path = std::env::temp_dir()
+        .join(format!("semi-honest-ecdsa-cli-{}.sock", std::process::id()));
+    let (a, b) = std::os::unix::net::UnixStream::pair().unwrap();
+    let (a, _b) = (
+        node::Node::new(a, true).unwrap(),
+        node::Node::new(b, false).unwrap(),
+    );
+    {
+        let path = path.clone();
+        std::thread::spawn(move || node::serve_control(&a, &path));
+    }
+    let control = path.to_str().unwrap().to_string();
+    let keygen = format!("keygen --json --control {}", control);
+    let mut out = vec![];
+    // the daemon may not be listening yet
+    let mut tries = 0;
+    while let Err(e) = run(args(&keygen), &mut out) {
+        tries += 1;
+        assert!(tries < 500, "daemon never answered: {}", e);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(out.starts_with(b"{\"key\":"));
+    let mut out = vec![];
+    run(args(&format!("peers --control {}", control)), &mut out).unwrap();
+    assert_eq!(out, b"peer - up\n");
+    let _ = std::fs::remove_file(&path);
+}
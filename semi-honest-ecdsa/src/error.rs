@@ -0,0 +1,80 @@
+use std::fmt;
+
+/// Error is anything that can stop a protocol run.
+///
+/// In the semi-honest model a failed run may have leaked the secrets it was
+/// using, so callers should discard keys and nonces involved in any run
+/// that returns an Error.
+#[derive(Debug)]
+pub enum Error {
+    /// The connection to a peer failed.
+    Io(std::io::Error),
+    /// A peer sent bytes which do not encode a valid curve point.
+    InvalidPoint,
+    /// A secp256k1 operation failed, e.g. a tweak reduced to zero.
+    Secp256k1(secp256k1::Error),
+    /// The protocol could not continue, with the reason why.
+    ProtocolAbort(&'static str),
+    /// The final signature did not verify against the aggregate key.
+    VerificationFailed,
+    /// A peer revealed a value which does not match its commitment.
+    PeerEquivocation,
+    /// A peer framed messages with a wire format version we do not speak.
+    UnsupportedVersion(u8),
+    /// A peer sent a frame other than the one the protocol expects next.
+    UnexpectedMessage { kind: u8, session: u32, seq: u32 },
+    /// A setting (e.g. a peer address) could not be understood.
+    Config(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::InvalidPoint => write!(f, "peer sent an invalid curve point"),
+            Error::Secp256k1(e) => write!(f, "secp256k1 error: {:?}", e),
+            Error::ProtocolAbort(why) => write!(f, "protocol aborted: {}", why),
+            Error::VerificationFailed => write!(f, "signature failed to verify"),
+            Error::PeerEquivocation => write!(f, "peer reveal does not match its commitment"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported wire format version {}", v),
+            Error::Config(why) => write!(f, "bad configuration: {}", why),
+            Error::UnexpectedMessage { kind, session, seq } => write!(
+                f,
+                "unexpected {:?} frame (tag {}) in session {:08x} at seq {}",
+                crate::wire::Kind::from_u8(*kind),
+                kind,
+                session,
+                seq
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Error {
+        Error::Secp256k1(e)
+    }
+}
+
+impl<T> From<std::sync::mpsc::SendError<T>> for Error {
+    fn from(_: std::sync::mpsc::SendError<T>) -> Error {
+        Error::ProtocolAbort("worker thread hung up")
+    }
+}
+
+/// joined unwraps the result of a protocol worker thread, turning a panic
+/// into an abort.
+pub fn joined<T>(h: std::thread::JoinHandle<Result<T>>) -> Result<T> {
+    h.join()
+        .map_err(|_| Error::ProtocolAbort("worker thread panicked"))?
+}
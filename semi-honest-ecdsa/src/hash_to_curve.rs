@@ -0,0 +1,459 @@
+//! Hashing to secp256k1 as the RFC 9380 suite secp256k1_XMD:SHA-256_SSWU_RO_.
+//!
+//! A message is expanded with expand_message_xmd into two field elements,
+//! each mapped by the simplified SWU map onto a curve 3-isogenous to
+//! secp256k1 and then across the isogeny, and the two points are added.
+//! secp256k1 has cofactor 1, so there is nothing to clear.
+//!
+//! The field arithmetic does the same operations whatever its inputs, and
+//! no input makes hash_to_curve panic.
+use crate::error::{Error, Result};
+use secp256k1::PublicKey;
+use sha2::{Digest, Sha256};
+
+/// Limbs of the field prime p, least significant first.
+const P: [u64; 4] = [
+    0xFFFFFFFEFFFFFC2F,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+];
+
+/// C is 2^256 - p, so 2^256 = C mod p.
+const C: u64 = 0x1000003D1;
+
+/* Exponents for inversion, square roots and the Legendre symbol. */
+const P_MINUS_2: [u64; 4] = [
+    0xFFFFFFFEFFFFFC2D,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+];
+const P_PLUS_1_OVER_4: [u64; 4] = [
+    0xFFFFFFFFBFFFFF0C,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0x3FFFFFFFFFFFFFFF,
+];
+const P_MINUS_1_OVER_2: [u64; 4] = [
+    0xFFFFFFFF7FFFFE17,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0x7FFFFFFFFFFFFFFF,
+];
+
+/* The isogenous curve y^2 = x^3 + A'x + B' and the SWU constant Z. */
+const A: Fe = Fe([
+    0x405447C01A444533,
+    0xE953D363CB6F0E5D,
+    0xA08A5558F0F5D272,
+    0x3F8731ABDD661ADC,
+]);
+const B: Fe = Fe([1771, 0, 0, 0]);
+const Z: Fe = Fe([
+    0xFFFFFFFEFFFFFC24,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+    0xFFFFFFFFFFFFFFFF,
+]);
+/* -B'/A' and B'/(Z A'), the two candidates for x1. */
+const NEG_B_OVER_A: Fe = Fe([
+    0xAA01D58E8D2345C3,
+    0x6DF4FF98E82D74FD,
+    0xA00C4DF5D3E87B0C,
+    0x0BC56CEE718538B2,
+]);
+const B_OVER_ZA: Fe = Fe([
+    0x6C8BCD97DE490391,
+    0x7E5C173C7232AD8B,
+    0x6BA4071659152275,
+    0xBB407E4438DD90CA,
+]);
+
+/* The 3-isogeny map's coefficients k_(i,j), lowest degree first. */
+const X_NUM: [Fe; 4] = [
+    Fe([0x8E38E38DAAAAA8C7, 0x38E38E38E38E38E3, 0xE38E38E38E38E38E, 0x8E38E38E38E38E38]),
+    Fe([0xDFFF1044F17C6581, 0xD595D2FC0BF63B92, 0xB9F315CEA7FD44C5, 0x07D3D4C80BC321D5]),
+    Fe([0x4ECBD0B53D9DD262, 0xE4506144037C4031, 0xE2A413DECA25CAEC, 0x534C328D23F234E6]),
+    Fe([0x8E38E38DAAAAA88C, 0x38E38E38E38E38E3, 0xE38E38E38E38E38E, 0x8E38E38E38E38E38]),
+];
+const X_DEN: [Fe; 3] = [
+    Fe([0x9FE6B745781EB49B, 0x86CD409542F8487D, 0x9CA34CCBB7B640DD, 0xD35771193D94918A]),
+    Fe([0xC52A56612A8C6D14, 0x06D36B641F5E41BB, 0xF7C4B2D51B542254, 0xEDADC6F64383DC1D]),
+    Fe::ONE,
+];
+const Y_NUM: [Fe; 4] = [
+    Fe([0xA12F684B8E38E23C, 0x2F684BDA12F684BD, 0x684BDA12F684BDA1, 0x4BDA12F684BDA12F]),
+    Fe([0xDFFC90FC201D71A3, 0x647AB046D686DA6F, 0xA9D0A54B12A0A6D5, 0xC75E0C32D5CB7C0F]),
+    Fe([0xA765E85A9ECEE931, 0x722830A201BE2018, 0x715209EF6512E576, 0x29A6194691F91A73]),
+    Fe([0x84BDA12F38E38D84, 0xBDA12F684BDA12F6, 0xA12F684BDA12F684, 0x2F684BDA12F684BD]),
+];
+const Y_DEN: [Fe; 4] = [
+    Fe([0xFFFFFFFEFFFFF93B, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF, 0xFFFFFFFFFFFFFFFF]),
+    Fe([0xDFB425D2685C2573, 0x9467C1BFC8E8D978, 0xD5E9E6632722C298, 0x7A06534BB8BDB49F]),
+    Fe([0xA7BF8192BFD2A76F, 0x0A3D21162F0D6299, 0xF3A70C3FA8FE337E, 0x6484AA716545CA2C]),
+    Fe::ONE,
+];
+
+/// Fe is an element of the field of integers mod p, always fully reduced.
+#[derive(Clone, Copy, Debug)]
+struct Fe([u64; 4]);
+
+/// zero_mask is all ones if x is 0 and 0 otherwise, without branching.
+fn zero_mask(x: u64) -> u64 {
+    ((x | x.wrapping_neg()) >> 63).wrapping_sub(1)
+}
+
+impl Fe {
+    const ZERO: Fe = Fe([0; 4]);
+    const ONE: Fe = Fe([1, 0, 0, 0]);
+
+    /// reduce takes carry 2^256 + a, which must be below 2p, mod p.
+    fn reduce(a: [u64; 4], carry: u64) -> Fe {
+        let mut t = [0u64; 4];
+        let mut borrow = 0u64;
+        for i in 0..4 {
+            let (d, b1) = a[i].overflowing_sub(P[i]);
+            let (d, b2) = d.overflowing_sub(borrow);
+            t[i] = d;
+            borrow = (b1 | b2) as u64;
+        }
+        // a - p is the answer unless it went negative without a carry in
+        let mask = (carry | (borrow ^ 1)).wrapping_neg();
+        Fe::select(mask, Fe(t), Fe(a))
+    }
+
+    /// from_bytes reads a big endian integer below 2^256, reducing it mod p.
+    fn from_bytes(b: &[u8; 32]) -> Fe {
+        let mut a = [0u64; 4];
+        for (i, limb) in a.iter_mut().enumerate() {
+            let mut w = [0u8; 8];
+            w.clone_from_slice(&b[24 - 8 * i..32 - 8 * i]);
+            *limb = u64::from_be_bytes(w);
+        }
+        Fe::reduce(a, 0)
+    }
+
+    /// from_wide_bytes reads a 48 byte big endian integer, reducing it mod p,
+    /// as hash_to_field does.
+    fn from_wide_bytes(b: &[u8; 48]) -> Fe {
+        let mut hi = [0u8; 32];
+        let mut lo = [0u8; 32];
+        hi[16..].clone_from_slice(&b[..16]);
+        lo.clone_from_slice(&b[16..]);
+        // hi 2^256 + lo = hi C + lo
+        Fe::from_bytes(&hi).mul(&Fe([C, 0, 0, 0])).add(&Fe::from_bytes(&lo))
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let mut b = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            b[24 - 8 * i..32 - 8 * i].clone_from_slice(&limb.to_be_bytes());
+        }
+        b
+    }
+
+    /// select is a where mask is all ones and b where it is 0.
+    fn select(mask: u64, a: Fe, b: Fe) -> Fe {
+        let mut r = a.0;
+        for (w, x) in r.iter_mut().zip(b.0.iter()) {
+            *w = (*w & mask) | (x & !mask);
+        }
+        Fe(r)
+    }
+
+    fn is_zero(&self) -> u64 {
+        zero_mask(self.0[0] | self.0[1] | self.0[2] | self.0[3])
+    }
+
+    fn ct_eq(&self, other: &Fe) -> u64 {
+        zero_mask(
+            (self.0[0] ^ other.0[0])
+                | (self.0[1] ^ other.0[1])
+                | (self.0[2] ^ other.0[2])
+                | (self.0[3] ^ other.0[3]),
+        )
+    }
+
+    /// sgn0 is the parity of the element, as RFC 9380 defines it for prime
+    /// fields.
+    fn sgn0(&self) -> u64 {
+        self.0[0] & 1
+    }
+
+    fn add(&self, other: &Fe) -> Fe {
+        let mut r = [0u64; 4];
+        let mut carry = 0u64;
+        for (w, (a, b)) in r.iter_mut().zip(self.0.iter().zip(other.0.iter())) {
+            let t = *a as u128 + *b as u128 + carry as u128;
+            *w = t as u64;
+            carry = (t >> 64) as u64;
+        }
+        Fe::reduce(r, carry)
+    }
+
+    fn neg(&self) -> Fe {
+        Fe::ZERO.sub(self)
+    }
+
+    fn sub(&self, other: &Fe) -> Fe {
+        let mut r = [0u64; 4];
+        let mut borrow = 0u64;
+        for (w, (a, b)) in r.iter_mut().zip(self.0.iter().zip(other.0.iter())) {
+            let (d, b1) = a.overflowing_sub(*b);
+            let (d, b2) = d.overflowing_sub(borrow);
+            *w = d;
+            borrow = (b1 | b2) as u64;
+        }
+        // add p back if we went negative
+        let mask = borrow.wrapping_neg();
+        let mut carry = 0u64;
+        for i in 0..4 {
+            let t = r[i] as u128 + (P[i] & mask) as u128 + carry as u128;
+            r[i] = t as u64;
+            carry = (t >> 64) as u64;
+        }
+        Fe(r)
+    }
+
+    fn mul(&self, other: &Fe) -> Fe {
+        let mut w = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let t = w[i + j] as u128 + self.0[i] as u128 * other.0[j] as u128 + carry;
+                w[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            w[i + 4] = carry as u64;
+        }
+        // lo + hi 2^256 = lo + hi C, leaving a carry below 2^34
+        let mut r = [0u64; 4];
+        let mut carry = 0u128;
+        for i in 0..4 {
+            let t = w[i] as u128 + w[i + 4] as u128 * C as u128 + carry;
+            r[i] = t as u64;
+            carry = t >> 64;
+        }
+        // and once more for the carry, which leaves at most 2^256 + 2^68
+        let mut t = carry * C as u128;
+        for limb in r.iter_mut() {
+            t += *limb as u128;
+            *limb = t as u64;
+            t >>= 64;
+        }
+        Fe::reduce(r, t as u64)
+    }
+
+    fn square(&self) -> Fe {
+        self.mul(self)
+    }
+
+    /// pow raises to a fixed exponent, squaring and multiplying for every
+    /// bit whatever its value.
+    fn pow(&self, e: &[u64; 4]) -> Fe {
+        let mut r = Fe::ONE;
+        for i in (0..256).rev() {
+            r = r.square();
+            let bit = (e[i / 64] >> (i % 64)) & 1;
+            r = Fe::select(bit.wrapping_neg(), r.mul(self), r);
+        }
+        r
+    }
+
+    /// inv0 is the inverse, taking 0 to 0.
+    fn inv0(&self) -> Fe {
+        self.pow(&P_MINUS_2)
+    }
+
+    /// sqrt is a square root if there is one; p = 3 mod 4.
+    fn sqrt(&self) -> Fe {
+        self.pow(&P_PLUS_1_OVER_4)
+    }
+
+    /// is_square is all ones if the element is a square, 0 included.
+    fn is_square(&self) -> u64 {
+        let l = self.pow(&P_MINUS_1_OVER_2);
+        l.is_zero() | l.ct_eq(&Fe::ONE)
+    }
+}
+
+/// expand_message_xmd is RFC 9380's expand_message_xmd with SHA-256, for
+/// the 96 bytes two field elements take.
+fn expand_message_xmd(msg: &[u8], dst: &[u8]) -> Result<[u8; 96]> {
+    if dst.len() > 255 {
+        return Err(Error::ProtocolAbort("hash to curve tag too long"));
+    }
+    let dst_prime = |h: Sha256| h.chain_update(dst).chain_update([dst.len() as u8]);
+    let b0 = dst_prime(
+        Sha256::new()
+            .chain_update([0u8; 64])
+            .chain_update(msg)
+            .chain_update(96u16.to_be_bytes())
+            .chain_update([0u8]),
+    )
+    .finalize();
+    let mut out = [0u8; 96];
+    let mut b = [0u8; 32];
+    for (i, chunk) in out.chunks_exact_mut(32).enumerate() {
+        // b_i = H(b_0 ^ b_{i-1} || i || DST'), with b_0 alone for b_1
+        for (w, x) in b.iter_mut().zip(b0.iter()) {
+            *w ^= x;
+        }
+        let bi = dst_prime(Sha256::new().chain_update(b).chain_update([i as u8 + 1])).finalize();
+        b.clone_from_slice(bi.as_slice());
+        chunk.clone_from_slice(&b[..]);
+    }
+    Ok(out)
+}
+
+/// hash_to_field is RFC 9380's hash_to_field for two elements of secp256k1's
+/// base field.
+fn hash_to_field(msg: &[u8], dst: &[u8]) -> Result<[Fe; 2]> {
+    let uniform = expand_message_xmd(msg, dst)?;
+    let mut u = [Fe::ZERO; 2];
+    for (e, chunk) in u.iter_mut().zip(uniform.chunks_exact(48)) {
+        let mut b = [0u8; 48];
+        b.clone_from_slice(chunk);
+        *e = Fe::from_wide_bytes(&b);
+    }
+    Ok(u)
+}
+
+/// map_to_curve is the simplified SWU map onto the isogenous curve followed
+/// by the 3-isogeny onto secp256k1, giving affine coordinates.
+fn map_to_curve(u: &Fe) -> (Fe, Fe) {
+    let curve = |x: &Fe| x.square().mul(x).add(&A.mul(x)).add(&B);
+    // tv1 = inv0(Z^2 u^4 + Z u^2)
+    let z_u2 = Z.mul(&u.square());
+    let tv1 = z_u2.square().add(&z_u2).inv0();
+    let x1 = NEG_B_OVER_A.mul(&Fe::ONE.add(&tv1));
+    let x1 = Fe::select(tv1.is_zero(), B_OVER_ZA, x1);
+    let x2 = z_u2.mul(&x1);
+    let (gx1, gx2) = (curve(&x1), curve(&x2));
+    let e1 = gx1.is_square();
+    let x = Fe::select(e1, x1, x2);
+    let y = Fe::select(e1, gx1, gx2).sqrt();
+    let y = Fe::select((u.sgn0() ^ y.sgn0()).wrapping_neg(), y.neg(), y);
+
+    let poly = |k: &[Fe]| k.iter().rev().fold(Fe::ZERO, |acc, c| acc.mul(&x).add(c));
+    let x_out = poly(&X_NUM).mul(&poly(&X_DEN).inv0());
+    let y_out = y.mul(&poly(&Y_NUM)).mul(&poly(&Y_DEN).inv0());
+    (x_out, y_out)
+}
+
+/// hash_to_curve hashes msg to a point of secp256k1 under the domain
+/// separation tag dst, which must be at most 255 bytes.
+///
+/// Besides a too long dst, it only fails if the two mapped points are
+/// opposite, as their sum is then the point at infinity, which no PublicKey
+/// can hold. That happens with negligible probability.
+pub fn hash_to_curve(msg: &[u8], dst: &[u8]) -> Result<PublicKey> {
+    let [u0, u1] = hash_to_field(msg, dst)?;
+    add(map_to_curve(&u0), map_to_curve(&u1))
+}
+
+/// add sums two affine points, doubling when they are equal, choosing
+/// between the two slopes without branching.
+fn add((x0, y0): (Fe, Fe), (x1, y1): (Fe, Fe)) -> Result<PublicKey> {
+    // addition, lambda = (y1 - y0) / (x1 - x0)
+    let dx = x1.sub(&x0);
+    let sum = y1.sub(&y0).mul(&dx.inv0());
+    // doubling, lambda = 3 x0^2 / 2 y0, as secp256k1 has a = 0
+    let x0_2 = x0.square();
+    let double = x0_2.add(&x0_2).add(&x0_2).mul(&y0.add(&y0).inv0());
+    let same_x = dx.is_zero();
+    if same_x & y0.add(&y1).is_zero() != 0 {
+        return Err(Error::ProtocolAbort("hash to curve mapped to the point at infinity"));
+    }
+    let lambda = Fe::select(same_x, double, sum);
+    let x = lambda.square().sub(&x0).sub(&x1);
+    let y = lambda.mul(&x0.sub(&x)).sub(&y0);
+    let mut b = [0x04u8; 65];
+    b[1..33].clone_from_slice(&x.to_bytes());
+    b[33..].clone_from_slice(&y.to_bytes());
+    PublicKey::from_slice(&b).map_err(|_| Error::InvalidPoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DST is the tag of RFC 9380's secp256k1_XMD:SHA-256_SSWU_RO_ test
+    /// vectors.
+    const DST: &[u8] = b"QUUX-V01-CS02-with-secp256k1_XMD:SHA-256_SSWU_RO_";
+
+    fn hex32(s: &str) -> [u8; 32] {
+        let mut b = [0u8; 32];
+        b.clone_from_slice(&crate::util::from_hex(s).unwrap());
+        b
+    }
+
+    #[test]
+    fn rfc9380_vectors() {
+        let vectors = [
+            (
+                &b""[..],
+                "6b0f9910dd2ba71c78f2ee9f04d73b5f4c5f7fc773a701abea1e573cab002fb3",
+                "1ae6c212e08fe1a5937f6202f929a2cc8ef4ee5b9782db68b0d5799fd8f09e16",
+                "c1cae290e291aee617ebaef1be6d73861479c48b841eaba9b7b5852ddfeb1346",
+                "64fa678e07ae116126f08b022a94af6de15985c996c3a91b64c406a960e51067",
+            ),
+            (
+                &b"abc"[..],
+                "128aab5d3679a1f7601e3bdf94ced1f43e491f544767e18a4873f397b08a2b61",
+                "5897b65da3b595a813d0fdcc75c895dc531be76a03518b044daaa0f2e4689e00",
+                "3377e01eab42db296b512293120c6cee72b6ecf9f9205760bd9ff11fb3cb2c4b",
+                "7f95890f33efebd1044d382a01b1bee0900fb6116f94688d487c6c7b9c8371f6",
+            ),
+        ];
+        for (msg, u0, u1, x, y) in vectors.iter() {
+            let u = hash_to_field(msg, DST).unwrap();
+            assert_eq!(u[0].to_bytes(), hex32(u0));
+            assert_eq!(u[1].to_bytes(), hex32(u1));
+            let p = hash_to_curve(msg, DST).unwrap().serialize_uncompressed();
+            assert_eq!(&p[1..33], &hex32(x)[..]);
+            assert_eq!(&p[33..], &hex32(y)[..]);
+        }
+    }
+
+    #[test]
+    fn field_arithmetic() {
+        let p_minus_1 = Fe::ZERO.sub(&Fe::ONE);
+        assert_eq!(p_minus_1.add(&Fe::ONE).is_zero(), !0);
+        assert_eq!(p_minus_1.square().ct_eq(&Fe::ONE), !0);
+        let b: [u8; 32] = rand::random();
+        let x = Fe::from_bytes(&b);
+        assert_eq!(x.mul(&x.inv0()).ct_eq(&Fe::ONE), x.is_zero() ^ !0);
+        let x2 = x.square();
+        assert_eq!(x2.is_square(), !0);
+        assert_eq!(x2.sqrt().square().ct_eq(&x2), !0);
+        // -1 is not a square mod p = 3 mod 4
+        assert_eq!(p_minus_1.is_square(), 0);
+        assert_eq!(Fe::ZERO.inv0().is_zero(), !0);
+    }
+
+    #[test]
+    fn separates_domains() {
+        let a = hash_to_curve(b"msg", b"one").unwrap();
+        assert_ne!(a, hash_to_curve(b"msg", b"two").unwrap());
+        assert_ne!(a, hash_to_curve(b"msh", b"one").unwrap());
+        assert!(hash_to_curve(b"msg", &[0u8; 256][..]).is_err());
+    }
+
+    #[test]
+    fn adds_equal_and_opposite_points() {
+        let ctx = &secp256k1::Secp256k1::new();
+        let b: [u8; 32] = rand::random();
+        let (x, y) = map_to_curve(&Fe::from_bytes(&b));
+        let mut g = [0x04u8; 65];
+        g[1..33].clone_from_slice(&x.to_bytes());
+        g[33..].clone_from_slice(&y.to_bytes());
+        let g = PublicKey::from_slice(&g).unwrap();
+        let mut two = [0u8; 32];
+        two[31] = 2;
+        let two = secp256k1::Scalar::from_be_bytes(two).unwrap();
+        assert_eq!(add((x, y), (x, y)).unwrap(), g.mul_tweak(ctx, &two).unwrap());
+        assert!(add((x, y), (x, y.neg())).is_err());
+    }
+}
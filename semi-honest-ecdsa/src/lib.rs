@@ -0,0 +1,36 @@
+//! Semi-honest N-party ECDSA over secp256k1.
+//!
+//! Participants generate keys and nonces together (`ecdsa::keygen`,
+//! `ecdsa::nonce`) and then sign, either two at a time (`ecdsa::twopc`) or
+//! in any number (`ecdsa::npc`), over any stream a `ReadWrite` wraps, a
+//! `mux::Mux` substream, or a `wire::Channel`. `node` runs a pair of
+//! signing daemons on top of these.
+extern crate futures;
+extern crate rand;
+extern crate secp256k1;
+extern crate sha2;
+extern crate zeroize;
+mod error;
+mod hash_to_curve;
+pub mod mux;
+pub mod net;
+pub mod node;
+mod protocol;
+mod scalars;
+mod util;
+pub mod wire;
+use crate::util::*;
+
+pub use crate::error::{Error, Result};
+pub use crate::protocol::{mult, ot};
+pub use crate::scalars::Scalar;
+pub use crate::util::{from_hex, to_hex, HasTryClone, ReadWrite, Shutdown};
+
+/// ecdsa is key generation, nonce generation and signing.
+pub mod ecdsa {
+    pub use crate::protocol::ecdsa::keygen::{self, KeyAggregation, KeyShare};
+    pub use crate::protocol::ecdsa::nonce::{self, NonceShare};
+    pub use crate::protocol::ecdsa::util::{background_inverse, background_inverse_service};
+    pub use crate::protocol::ecdsa::util::Inverse;
+    pub use crate::protocol::ecdsa::{npc, twopc};
+}
@@ -0,0 +1,258 @@
+use crate::util::{HasTryClone, ReadWrite, Shutdown};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+#[cfg(test)]
+mod tests;
+
+/// MAX_CHUNK bounds the payload of one mux frame, so a peer can only make us
+/// allocate this much before we have seen the bytes.
+pub const MAX_CHUNK: usize = 1 << 16;
+
+/// MAX_SUBSTREAMS bounds the substreams with a queue at once, whether we
+/// opened them or the peer named them first. A peer naming one more ends the
+/// connection.
+pub const MAX_SUBSTREAMS: usize = 64;
+
+/// MAX_QUEUED bounds the bytes queued for one substream that it has not read
+/// yet. A peer sending more ends the connection.
+pub const MAX_QUEUED: usize = 1 << 21;
+
+/// MAX_DROPPED bounds how many dropped substream ids are remembered, so
+/// chunks the peer still sends them are discarded rather than queued.
+pub const MAX_DROPPED: usize = 1024;
+
+/// A mux frame header is the substream id then the chunk length, both big
+/// endian.
+const HEADER_LEN: usize = 8;
+
+/// Queue is what has arrived for one substream, and how many handles to it
+/// are open.
+#[derive(Default)]
+struct Queue {
+    bytes: VecDeque<u8>,
+    handles: usize,
+}
+
+#[derive(Default)]
+struct Inbox {
+    queues: HashMap<u32, Queue>,
+    /// the most recently dropped substreams, oldest first
+    dropped: VecDeque<u32>,
+    /// set once the connection has failed or closed
+    closed: bool,
+}
+
+/// Incoming is the half of a Mux the demux thread shares.
+#[derive(Default)]
+struct Incoming {
+    inbox: Mutex<Inbox>,
+    arrived: Condvar,
+}
+
+/// Connection is the half of the stream a Mux writes to and hangs up.
+trait Connection: Write + Shutdown + Send {}
+impl<T: Write + Shutdown + Send> Connection for T {}
+
+struct Shared {
+    writer: Mutex<Box<dyn Connection>>,
+    incoming: Arc<Incoming>,
+}
+
+impl Drop for Shared {
+    /// The last handle is gone, so hang up, which also ends the demux thread.
+    fn drop(&mut self) {
+        let writer = self.writer.get_mut().unwrap_or_else(|e| e.into_inner());
+        let _ = writer.flush();
+        let _ = writer.shutdown();
+    }
+}
+
+/// Mux runs any number of substreams over one connection.
+///
+/// Each substream is a byte stream of its own, so a protocol run that would
+/// otherwise need a socket to itself (e.g. a multiplication, which clones
+/// its peer and reads and writes from several threads) can be given a
+/// substream instead and run alongside others. Both ends must agree on
+/// which substream id carries which run.
+///
+/// A background thread reads the connection and queues each chunk for its
+/// substream, whether or not it has been opened yet, until the peer hangs
+/// up or oversteps MAX_SUBSTREAMS or MAX_QUEUED. There is no backpressure,
+/// so one slow reader letting MAX_QUEUED bytes pile up tears down every
+/// substream. A substream's queue goes when its last handle drops, and
+/// chunks still arriving for it are discarded. The connection is shut down
+/// when the last handle to the Mux or any of its substreams drops.
+#[derive(Clone)]
+pub struct Mux {
+    shared: Arc<Shared>,
+}
+
+impl Mux {
+    pub fn new<T>(stream: T) -> Result<Mux>
+    where
+        T: 'static + ReadWrite + HasTryClone + Shutdown,
+    {
+        let reader = stream.try_clone()?;
+        let incoming = Arc::new(Incoming::default());
+        let shared = Arc::new(Shared {
+            writer: Mutex::new(Box::new(stream)),
+            incoming: Arc::clone(&incoming),
+        });
+        // The demux thread holds no handle to the writer, so it cannot keep
+        // the connection open
+        thread::spawn(move || {
+            // Any error ends the connection for every substream
+            let _ = demux_loop(reader, &incoming);
+            lock(&incoming.inbox).closed = true;
+            incoming.arrived.notify_all();
+        });
+        Ok(Mux { shared })
+    }
+
+    /// is_closed says whether the connection has failed or the peer hung up.
+    pub fn is_closed(&self) -> bool {
+        lock(&self.shared.incoming.inbox).closed
+    }
+
+    /// open returns substream `id`. Opening an id twice gives two handles to
+    /// the same substream, like try_clone.
+    pub fn open(&self, id: u32) -> Substream {
+        Substream::new(id, &self.shared)
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // A panicking holder cannot leave the queues half written, so carry on
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn demux_loop<T: Read>(mut reader: T, incoming: &Incoming) -> Result<()> {
+    let mut chunk = vec![0u8; MAX_CHUNK];
+    loop {
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header[..])?;
+        let mut id = [0u8; 4];
+        id.clone_from_slice(&header[..4]);
+        let mut len = [0u8; 4];
+        len.clone_from_slice(&header[4..]);
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_CHUNK {
+            return Err(Error::new(ErrorKind::InvalidData, "mux chunk too large"));
+        }
+        reader.read_exact(&mut chunk[..len])?;
+        let id = u32::from_be_bytes(id);
+        let mut inbox = lock(&incoming.inbox);
+        if inbox.dropped.contains(&id) {
+            continue;
+        }
+        let full = inbox.queues.len() >= MAX_SUBSTREAMS;
+        let queue = match inbox.queues.entry(id) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) if !full => e.insert(Queue::default()),
+            Entry::Vacant(_) => {
+                return Err(Error::new(ErrorKind::InvalidData, "too many mux substreams"));
+            }
+        };
+        if queue.bytes.len() + len > MAX_QUEUED {
+            return Err(Error::new(ErrorKind::InvalidData, "mux substream queue full"));
+        }
+        queue.bytes.extend(&chunk[..len]);
+        incoming.arrived.notify_all();
+    }
+}
+
+/// A Substream is one logical connection carried by a Mux.
+pub struct Substream {
+    id: u32,
+    shared: Arc<Shared>,
+}
+
+impl Substream {
+    fn new(id: u32, shared: &Arc<Shared>) -> Substream {
+        let mut inbox = lock(&shared.incoming.inbox);
+        inbox.dropped.retain(|d| *d != id);
+        inbox.queues.entry(id).or_default().handles += 1;
+        drop(inbox);
+        Substream {
+            id,
+            shared: Arc::clone(shared),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for Substream {
+    fn drop(&mut self) {
+        let mut inbox = lock(&self.shared.incoming.inbox);
+        if let Entry::Occupied(mut e) = inbox.queues.entry(self.id) {
+            e.get_mut().handles -= 1;
+            if e.get().handles == 0 {
+                e.remove();
+                if inbox.dropped.len() == MAX_DROPPED {
+                    inbox.dropped.pop_front();
+                }
+                inbox.dropped.push_back(self.id);
+            }
+        }
+    }
+}
+
+impl Read for Substream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let incoming = &self.shared.incoming;
+        let mut inbox = lock(&incoming.inbox);
+        loop {
+            let closed = inbox.closed;
+            if let Some(queue) = inbox.queues.get_mut(&self.id) {
+                if !queue.bytes.is_empty() {
+                    let n = std::cmp::min(buf.len(), queue.bytes.len());
+                    for (w, b) in buf.iter_mut().zip(queue.bytes.drain(..n)) {
+                        *w = b;
+                    }
+                    return Ok(n);
+                }
+            }
+            if closed {
+                return Ok(0);
+            }
+            inbox = incoming
+                .arrived
+                .wait(inbox)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+impl Write for Substream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = std::cmp::min(buf.len(), MAX_CHUNK);
+        let mut header = [0u8; HEADER_LEN];
+        header[..4].clone_from_slice(&self.id.to_be_bytes());
+        header[4..].clone_from_slice(&(n as u32).to_be_bytes());
+        let mut writer = lock(&self.shared.writer);
+        writer.write_all(&header[..])?;
+        writer.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        lock(&self.shared.writer).flush()
+    }
+}
+
+impl ReadWrite for Substream {}
+
+impl HasTryClone for Substream {
+    fn try_clone(&self) -> Result<Self> {
+        Ok(Substream::new(self.id, &self.shared))
+    }
+}
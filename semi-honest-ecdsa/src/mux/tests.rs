@@ -0,0 +1,155 @@
+use super::*;
+use crate::protocol;
+use crate::wire::Framed;
+use std::os::unix::net::UnixStream;
+
+fn pair() -> (Mux, Mux) {
+    let (a, b) = UnixStream::pair().unwrap();
+    (Mux::new(a).unwrap(), Mux::new(b).unwrap())
+}
+
+#[test]
+fn substreams_are_independent() {
+    let (a, b) = pair();
+    let (mut a1, mut a2) = (a.open(1), a.open(2));
+    let (mut b1, mut b2) = (b.open(1), b.open(2));
+    a2.write_all(b"second").unwrap();
+    a1.write_all(b"first").unwrap();
+    b2.write_all(b"reply").unwrap();
+    let mut buf = [0u8; 5];
+    b1.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"first");
+    a2.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"reply");
+    let mut buf = [0u8; 6];
+    b2.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"second");
+}
+
+#[test]
+fn hang_up_ends_substreams() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let mux = Mux::new(a).unwrap();
+    let mut sub = mux.open(0);
+    drop(b);
+    let mut buf = [0u8; 1];
+    assert!(sub.read_exact(&mut buf).is_err());
+}
+
+#[test]
+fn concurrent_multiplications() {
+    let (a, b) = pair();
+    let runs: Vec<_> = (0..4u32)
+        .map(|id| {
+            let alpha = crate::scalars::Scalar::random();
+            let beta = crate::scalars::Scalar::random();
+            let radix = protocol::mult::Radix::default();
+            let (share_a, sent) = protocol::mult::sender::run_scale_free(
+                &alpha,
+                radix,
+                Framed::new(a.open(id), id),
+            );
+            let received = protocol::mult::receiver::run_scale_free(
+                &beta,
+                radix,
+                Framed::new(b.open(id), id),
+            );
+            (alpha, beta, share_a, sent, received)
+        })
+        .collect();
+    for (alpha, beta, share_a, sent, received) in runs {
+        sent.join().unwrap().unwrap();
+        let share_b = received.join().unwrap().unwrap();
+        assert_eq!(share_a + share_b, alpha * beta);
+    }
+}
+
+/// closes waits up to a few seconds for mux to see the connection end.
+fn closes(mux: &Mux) -> bool {
+    for _ in 0..500 {
+        if mux.is_closed() {
+            return true;
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+    }
+    false
+}
+
+/// chunk writes one raw mux frame to a peer's connection.
+fn chunk(peer: &mut UnixStream, id: u32, payload: &[u8]) {
+    peer.write_all(&id.to_be_bytes()).unwrap();
+    peer.write_all(&(payload.len() as u32).to_be_bytes()).unwrap();
+    peer.write_all(payload).unwrap();
+}
+
+#[test]
+fn dropping_every_handle_hangs_up() {
+    let (a, mut b) = UnixStream::pair().unwrap();
+    b.set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let mux = Mux::new(a).unwrap();
+    let sub = mux.open(1);
+    drop(mux);
+    drop(sub);
+    let mut buf = [0u8; 1];
+    assert_eq!(b.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn dropping_a_substream_frees_its_queue() {
+    let (a, mut b) = UnixStream::pair().unwrap();
+    let mux = Mux::new(a).unwrap();
+    let mut sub = mux.open(7);
+    let again = sub.try_clone().unwrap();
+    chunk(&mut b, 7, b"hi");
+    let mut buf = [0u8; 1];
+    sub.read_exact(&mut buf).unwrap();
+    drop(sub);
+    assert!(lock(&mux.shared.incoming.inbox).queues.contains_key(&7));
+    drop(again);
+    assert!(!lock(&mux.shared.incoming.inbox).queues.contains_key(&7));
+}
+
+#[test]
+fn chunks_for_dropped_substreams_are_discarded() {
+    let (a, mut b) = UnixStream::pair().unwrap();
+    let mux = Mux::new(a).unwrap();
+    for id in 0..MAX_SUBSTREAMS as u32 * 2 {
+        drop(mux.open(id));
+        chunk(&mut b, id, b"late");
+    }
+    // a chunk for a live substream after them all still arrives
+    let mut sub = mux.open(MAX_SUBSTREAMS as u32 * 2);
+    chunk(&mut b, sub.id(), b"x");
+    let mut buf = [0u8; 1];
+    sub.read_exact(&mut buf).unwrap();
+    assert!(!mux.is_closed());
+    assert_eq!(lock(&mux.shared.incoming.inbox).queues.len(), 1);
+}
+
+#[test]
+fn too_many_substreams() {
+    let (a, mut b) = UnixStream::pair().unwrap();
+    let mux = Mux::new(a).unwrap();
+    for id in 0..MAX_SUBSTREAMS as u32 {
+        chunk(&mut b, id, b"x");
+    }
+    thread::sleep(std::time::Duration::from_millis(100));
+    assert!(!mux.is_closed());
+    chunk(&mut b, MAX_SUBSTREAMS as u32, b"x");
+    assert!(closes(&mux));
+}
+
+#[test]
+fn unread_bytes_are_bounded() {
+    let (a, mut b) = UnixStream::pair().unwrap();
+    let mux = Mux::new(a).unwrap();
+    let payload = vec![0u8; MAX_CHUNK];
+    for _ in 0..MAX_QUEUED / MAX_CHUNK {
+        chunk(&mut b, 3, &payload[..]);
+    }
+    thread::sleep(std::time::Duration::from_millis(100));
+    assert!(!mux.is_closed());
+    chunk(&mut b, 3, b"x");
+    assert!(closes(&mux));
+}
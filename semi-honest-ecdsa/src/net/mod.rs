@@ -0,0 +1,100 @@
+use crate::error::{Error, Result};
+use std::fmt;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+#[cfg(test)]
+mod tests;
+
+/// CONNECT_ATTEMPTS and CONNECT_BACKOFF let both parties be started in any
+/// order: the connecting side keeps retrying while the other starts up.
+pub const CONNECT_ATTEMPTS: u32 = 20;
+pub const CONNECT_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Peer says how to reach the other party of a run.
+///
+/// It parses from `listen:ADDR` or `connect:ADDR`, where ADDR is anything
+/// ToSocketAddrs resolves, e.g. `connect:signer-b.local:7000`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Peer {
+    /// wait for the other party to connect to this address
+    Listen(SocketAddr),
+    /// connect out to the other party at this address
+    Connect(SocketAddr),
+}
+
+impl Peer {
+    /// establish returns a connection to the other party, ready to pass to
+    /// e.g. twopc::run.
+    pub fn establish(&self) -> Result<TcpStream> {
+        match *self {
+            Peer::Listen(addr) => accept(&TcpListener::bind(addr)?),
+            Peer::Connect(addr) => connect(addr),
+        }
+    }
+}
+
+/// Peers print as they parse.
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Peer::Listen(addr) => write!(f, "listen:{}", addr),
+            Peer::Connect(addr) => write!(f, "connect:{}", addr),
+        }
+    }
+}
+
+impl FromStr for Peer {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Peer> {
+        let (how, addr) = match s.find(':') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => {
+                return Err(Error::Config(format!(
+                    "{}: expected listen:ADDR or connect:ADDR",
+                    s
+                )))
+            }
+        };
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(|e| Error::Config(format!("{}: {}", addr, e)))?
+            .next()
+            .ok_or_else(|| Error::Config(format!("{}: no addresses", addr)))?;
+        match how {
+            "listen" => Ok(Peer::Listen(addr)),
+            "connect" => Ok(Peer::Connect(addr)),
+            _ => Err(Error::Config(format!("{}: expected listen or connect", how))),
+        }
+    }
+}
+
+/// accept waits for one connection on listener.
+pub fn accept(listener: &TcpListener) -> Result<TcpStream> {
+    let (stream, _) = listener.accept()?;
+    configure(stream)
+}
+
+/// connect dials addr, retrying while nothing is listening there yet.
+pub fn connect(addr: SocketAddr) -> Result<TcpStream> {
+    let mut attempts = 0;
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return configure(stream),
+            Err(e) => {
+                attempts += 1;
+                if attempts >= CONNECT_ATTEMPTS {
+                    return Err(e.into());
+                }
+                std::thread::sleep(CONNECT_BACKOFF);
+            }
+        }
+    }
+}
+
+fn configure(stream: TcpStream) -> Result<TcpStream> {
+    // Protocol messages are small and each one waits on a reply, so Nagle
+    // would only add latency
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
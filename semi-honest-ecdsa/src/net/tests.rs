@@ -0,0 +1,34 @@
+use super::*;
+use crate::protocol;
+
+#[test]
+fn parse_peer() {
+    assert_eq!(
+        "listen:127.0.0.1:7000".parse::<Peer>().unwrap(),
+        Peer::Listen("127.0.0.1:7000".parse().unwrap())
+    );
+    assert_eq!(
+        "connect:[::1]:7000".parse::<Peer>().unwrap(),
+        Peer::Connect("[::1]:7000".parse().unwrap())
+    );
+    assert!("127.0.0.1:7000".parse::<Peer>().is_err());
+    assert!("dial:127.0.0.1:7000".parse::<Peer>().is_err());
+    let peer = Peer::Connect("127.0.0.1:7000".parse().unwrap());
+    assert_eq!(peer.to_string().parse::<Peer>().unwrap(), peer);
+}
+
+#[test]
+fn twopc_over_loopback_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let m = crate::scalars::Scalar::random();
+    let h = std::thread::spawn(move || {
+        let peer = Peer::Connect(addr).establish().unwrap();
+        protocol::ecdsa::twopc::run(protocol::ecdsa::util::background_inverse, &m, peer)
+    });
+    let peer = accept(&listener).unwrap();
+    let sig = protocol::ecdsa::twopc::run(protocol::ecdsa::util::background_inverse, &m, peer);
+    let peer_sig = h.join().unwrap();
+    assert!(sig.is_ok());
+    assert_eq!(sig.ok(), peer_sig.ok());
+}
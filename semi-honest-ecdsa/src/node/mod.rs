@@ -0,0 +1,529 @@
+use crate::error::{Error, Result};
+use crate::mux::{Mux, Substream};
+use crate::net::Peer;
+use crate::protocol::ecdsa::keygen::KeyShare;
+use crate::protocol::ecdsa::nonce::{self, NonceShare};
+use crate::protocol::ecdsa::{npc, twopc, util::background_inverse};
+use crate::util::{from_hex, to_hex, HasTryClone, ReadWrite, Shutdown};
+use crate::wire::{Framed, Kind};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+#[cfg(test)]
+mod tests;
+
+/// REQUESTS is the substream on which each node asks the other to join
+/// sessions. Every session then runs on a substream of its own, numbered
+/// odd by the connecting node and even by the listening one so the two
+/// never pick the same id.
+const REQUESTS: u32 = 0;
+
+/// A request frame is the op, the session's substream id, the key id, the
+/// nonce id (0 for none) and the message, the ids big endian.
+const REQUEST_LEN: usize = 1 + 4 + 4 + 4 + 32;
+
+/// Request is a session one node asks the other to join.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// generate a key, known to both nodes by the id of its session
+    Keygen,
+    /// generate a nonce ahead of signing, known to both nodes by the id of
+    /// its session
+    Nonce,
+    /// sign m under the key generated in session `key`, with the nonce
+    /// generated in session `nonce` if any, which is then used up
+    Sign {
+        key: u32,
+        nonce: Option<u32>,
+        m: crate::scalars::Scalar,
+    },
+}
+
+impl Request {
+    fn to_bytes(self, id: u32) -> [u8; REQUEST_LEN] {
+        let mut b = [0u8; REQUEST_LEN];
+        b[1..5].clone_from_slice(&id.to_be_bytes());
+        match self {
+            Request::Keygen => {}
+            Request::Nonce => b[0] = 1,
+            Request::Sign { key, nonce, m } => {
+                b[0] = 2;
+                b[5..9].clone_from_slice(&key.to_be_bytes());
+                b[9..13].clone_from_slice(&nonce.unwrap_or(0).to_be_bytes());
+                b[13..].clone_from_slice(&m.to_bytes()[..]);
+            }
+        }
+        b
+    }
+
+    fn from_bytes(b: &[u8; REQUEST_LEN]) -> Result<(u32, Request)> {
+        let word = |at: usize| {
+            let mut w = [0u8; 4];
+            w.clone_from_slice(&b[at..at + 4]);
+            u32::from_be_bytes(w)
+        };
+        let mut m = [0u8; 32];
+        m.clone_from_slice(&b[13..]);
+        let request = match b[0] {
+            0 => Request::Keygen,
+            1 => Request::Nonce,
+            2 => Request::Sign {
+                key: word(5),
+                // substream 0 carries requests, so no session has id 0
+                nonce: Some(word(9)).filter(|&n| n != REQUESTS),
+                m: crate::scalars::Scalar::from_bytes(&m),
+            },
+            _ => return Err(Error::ProtocolAbort("unknown session request")),
+        };
+        Ok((word(1), request))
+    }
+}
+
+/// Command is one line sent to the control socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// start a session with the peer
+    Run(Request),
+    /// let the peer start this Sign session once, see Node
+    Approve(Request),
+    /// report on the peer connection
+    Peers,
+}
+
+/// Commands parse from `keygen`, `nonce`, `sign KEY HEX [NONCE]` where HEX
+/// is the 32 byte message, `approve KEY HEX [NONCE]` or `peers`.
+impl FromStr for Command {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Command> {
+        let id = |w: &str| {
+            w.parse::<u32>()
+                .map_err(|_| Error::Config(format!("{}: not a session id", w)))
+        };
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let request = match &words[..] {
+            ["keygen"] => Request::Keygen,
+            ["nonce"] => Request::Nonce,
+            ["sign" | "approve", key, m] | ["sign" | "approve", key, m, _] => Request::Sign {
+                key: id(key)?,
+                nonce: match words.get(3) {
+                    Some(nonce) => Some(id(nonce)?),
+                    None => None,
+                },
+                m: crate::scalars::Scalar::from_bytes(&b32_from_hex(m)?),
+            },
+            ["peers"] => return Ok(Command::Peers),
+            _ => {
+                return Err(Error::Config(format!(
+                    "{}: expected keygen, nonce, sign KEY HEX [NONCE], \
+                     approve KEY HEX [NONCE] or peers",
+                    s
+                )))
+            }
+        };
+        if words.first() == Some(&"approve") {
+            return Ok(Command::Approve(request));
+        }
+        Ok(Command::Run(request))
+    }
+}
+
+fn b32_from_hex(s: &str) -> Result<[u8; 32]> {
+    let b = from_hex(s)?;
+    if b.len() != 32 {
+        return Err(Error::Config(format!("{}: expected 32 bytes", s)));
+    }
+    let mut b32 = [0u8; 32];
+    b32.clone_from_slice(&b[..]);
+    Ok(b32)
+}
+
+/// Reply is what a command produced.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    /// a new key's id and aggregate public key
+    Key {
+        id: u32,
+        pubkey: secp256k1::PublicKey,
+    },
+    /// a new nonce's id and its point K G
+    Nonce {
+        id: u32,
+        point: secp256k1::PublicKey,
+    },
+    /// a signature, which has already been verified
+    Signature(secp256k1::ecdsa::Signature),
+    /// how the peer is reached, if known, and whether it is still connected
+    Peer { peer: Option<Peer>, up: bool },
+    /// the peer may now start the approved session
+    Approved,
+}
+
+/// Replies print as the control socket's answer lines: `key ID HEX`,
+/// `nonce ID HEX`, `signature HEX` with the signature in compact form,
+/// `peer PEER up|down` with `-` for a peer we were handed a connection to,
+/// or `approved`.
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reply::Key { id, pubkey } => write!(f, "key {} {}", id, to_hex(&pubkey.serialize())),
+            Reply::Nonce { id, point } => write!(f, "nonce {} {}", id, to_hex(&point.serialize())),
+            Reply::Signature(sig) => write!(f, "signature {}", to_hex(&sig.serialize_compact())),
+            Reply::Peer { peer, up } => match peer {
+                Some(peer) => write!(f, "peer {} {}", peer, if *up { "up" } else { "down" }),
+                None => write!(f, "peer - {}", if *up { "up" } else { "down" }),
+            },
+            Reply::Approved => write!(f, "approved"),
+        }
+    }
+}
+
+/// Replies parse from what they print, for control clients.
+impl FromStr for Reply {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Reply> {
+        let id = |w: &str| {
+            w.parse::<u32>()
+                .map_err(|_| Error::Config(format!("{}: not a session id", w)))
+        };
+        let point = |w: &str| {
+            secp256k1::PublicKey::from_slice(&from_hex(w)?[..]).map_err(|_| Error::InvalidPoint)
+        };
+        let words: Vec<&str> = s.split_whitespace().collect();
+        match &words[..] {
+            ["key", i, pubkey] => Ok(Reply::Key {
+                id: id(i)?,
+                pubkey: point(pubkey)?,
+            }),
+            ["nonce", i, p] => Ok(Reply::Nonce {
+                id: id(i)?,
+                point: point(p)?,
+            }),
+            ["signature", sig] => Ok(Reply::Signature(
+                secp256k1::ecdsa::Signature::from_compact(&from_hex(sig)?[..])?,
+            )),
+            ["peer", peer, up] => Ok(Reply::Peer {
+                peer: match *peer {
+                    "-" => None,
+                    peer => Some(peer.parse()?),
+                },
+                up: *up == "up",
+            }),
+            ["approved"] => Ok(Reply::Approved),
+            _ => Err(Error::Config(format!("{}: not a reply", s))),
+        }
+    }
+}
+
+impl Reply {
+    /// to_json is the reply as a JSON object, for scripts.
+    pub fn to_json(&self) -> String {
+        match self {
+            Reply::Key { id, pubkey } => format!(
+                "{{\"key\":{},\"pubkey\":\"{}\"}}",
+                id,
+                to_hex(&pubkey.serialize())
+            ),
+            Reply::Nonce { id, point } => format!(
+                "{{\"nonce\":{},\"point\":\"{}\"}}",
+                id,
+                to_hex(&point.serialize())
+            ),
+            Reply::Signature(sig) => format!(
+                "{{\"signature\":\"{}\"}}",
+                to_hex(&sig.serialize_compact())
+            ),
+            Reply::Peer { peer, up } => format!(
+                "{{\"peer\":{},\"up\":{}}}",
+                match peer {
+                    Some(peer) => format!("\"{}\"", peer),
+                    None => "null".to_string(),
+                },
+                up
+            ),
+            Reply::Approved => "{\"approved\":true}".to_string(),
+        }
+    }
+}
+
+/// Node is one of a pair of signing daemons.
+///
+/// Either node may start a session; it asks its peer to join, then both run
+/// their side of it on a fresh substream of their one connection, so any
+/// number of sessions can run at once. Keys and nonces live in memory
+/// only, under the id of the session which generated them.
+///
+/// The peer may start keygen and nonce sessions at will, but a node only
+/// joins a Sign session its own operator approved, each approval allowing
+/// exactly one such session. Otherwise the peer could have us sign anything.
+#[derive(Clone)]
+pub struct Node {
+    mux: Mux,
+    /// how the peer was reached, if we did so
+    peer: Option<Peer>,
+    /// our participant index in N-party sessions, 0 for the connecting node
+    me: usize,
+    requests: Arc<Mutex<Framed<Substream>>>,
+    keys: Arc<Mutex<HashMap<u32, KeyShare>>>,
+    nonces: Arc<Mutex<HashMap<u32, NonceShare>>>,
+    /// Sign sessions the peer may start, each once
+    approved: Arc<Mutex<Vec<Request>>>,
+    next_id: Arc<AtomicU32>,
+}
+
+// The N-party node this grows into keeps the structure sketched when the
+// binary was empty:
+//
+//     Each nonce inverse share listens, multiplying for any inbound
+//     request. Each key share connects to the i-th nonce share and
+//     initiates a multiplication, which returns immediately because its
+//     value is independent of what the counterparty learns. Before moving
+//     on to the i+1st nonce all n-1 multiplications must complete and be
+//     summed, so the latency is O(n) with n-1 sockets open at once.
+//
+//     Signing a tree of presigned transactions creates
+//     T(N) = 2T(N/2) + (N-1)^2 = O(2N^2) threads in total, O(2N) per
+//     participant, which a shared semaphore may cap.
+
+impl Node {
+    /// establish connects to peer and starts serving its requests.
+    pub fn establish(peer: &Peer) -> Result<Node> {
+        let connecting = match peer {
+            Peer::Connect(_) => true,
+            Peer::Listen(_) => false,
+        };
+        let mut node = Node::new(peer.establish()?, connecting)?;
+        node.peer = Some(*peer);
+        Ok(node)
+    }
+
+    /// new runs a node over an established connection. Exactly one of the
+    /// two nodes must be `connecting`.
+    pub fn new<T>(stream: T, connecting: bool) -> Result<Node>
+    where
+        T: 'static + ReadWrite + HasTryClone + Shutdown,
+    {
+        let mux = Mux::new(stream)?;
+        let node = Node {
+            requests: Arc::new(Mutex::new(Framed::new(mux.open(REQUESTS), REQUESTS))),
+            mux,
+            peer: None,
+            me: if connecting { 0 } else { 1 },
+            keys: Arc::new(Mutex::new(HashMap::new())),
+            nonces: Arc::new(Mutex::new(HashMap::new())),
+            approved: Arc::new(Mutex::new(Vec::new())),
+            next_id: Arc::new(AtomicU32::new(if connecting { 1 } else { 2 })),
+        };
+        let server = node.clone();
+        let incoming = Framed::new(server.mux.open(REQUESTS), REQUESTS);
+        thread::spawn(move || server.serve(incoming));
+        Ok(node)
+    }
+
+    /// command carries out a control socket command.
+    pub fn command(&self, command: Command) -> Result<Reply> {
+        match command {
+            Command::Run(request) => self.run(request),
+            Command::Approve(request) => {
+                self.approve(request);
+                Ok(Reply::Approved)
+            }
+            Command::Peers => Ok(Reply::Peer {
+                peer: self.peer,
+                up: !self.mux.is_closed(),
+            }),
+        }
+    }
+
+    /// run starts a session and waits for its result.
+    pub fn run(&self, request: Request) -> Result<Reply> {
+        self.check(request)?;
+        let id = self.next_id.fetch_add(2, Ordering::SeqCst);
+        let mut stream = self.mux.open(id);
+        lock(&self.requests).send(Kind::Request, &request.to_bytes(id)[..])?;
+        let mut accepted = [0u8; 1];
+        Framed::new(&mut stream, id).recv(Kind::Accept, &mut accepted[..])?;
+        if accepted[0] != 1 {
+            return Err(Error::ProtocolAbort("peer refused the session"));
+        }
+        self.session(id, request, stream)
+    }
+
+    /// approve lets the peer start a session for request once.
+    pub fn approve(&self, request: Request) {
+        lock(&self.approved).push(request);
+    }
+
+    /// consent uses up our approval of request if the peer may not start it
+    /// at will, failing if there is none.
+    fn consent(&self, request: Request) -> Result<()> {
+        if let Request::Sign { .. } = request {
+            let mut approved = lock(&self.approved);
+            let at = approved
+                .iter()
+                .position(|a| *a == request)
+                .ok_or(Error::ProtocolAbort("signing not approved"))?;
+            approved.remove(at);
+        }
+        Ok(())
+    }
+
+    /// key returns a copy of the share of key `id`.
+    pub fn key(&self, id: u32) -> Result<KeyShare> {
+        lock(&self.keys)
+            .get(&id)
+            .cloned()
+            .ok_or(Error::ProtocolAbort("no such key"))
+    }
+
+    /// check fails if we cannot take part in request.
+    fn check(&self, request: Request) -> Result<()> {
+        if let Request::Sign { key, nonce, .. } = request {
+            if !lock(&self.keys).contains_key(&key) {
+                return Err(Error::ProtocolAbort("no such key"));
+            }
+            if let Some(nonce) = nonce {
+                if !lock(&self.nonces).contains_key(&nonce) {
+                    return Err(Error::ProtocolAbort("no such nonce"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn serve(self, mut incoming: Framed<Substream>) {
+        let mut buf = [0u8; REQUEST_LEN];
+        // Once the peer hangs up or breaks framing there is nothing left to
+        // serve
+        while incoming.recv(Kind::Request, &mut buf[..]).is_ok() {
+            match Request::from_bytes(&buf) {
+                Ok((id, request)) => {
+                    let node = self.clone();
+                    thread::spawn(move || node.respond(id, request));
+                }
+                // Refuse what we cannot parse so the peer is not left waiting
+                // on our answer
+                Err(_) => {
+                    let mut id = [0u8; 4];
+                    id.clone_from_slice(&buf[1..5]);
+                    let id = u32::from_be_bytes(id);
+                    if id != REQUESTS {
+                        let mut stream = self.mux.open(id);
+                        let _ = Framed::new(&mut stream, id).send(Kind::Accept, &[0]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// respond runs our side of a session the peer asked for.
+    fn respond(&self, id: u32, request: Request) -> Result<Reply> {
+        let mut stream = self.mux.open(id);
+        let check = self.check(request).and_then(|()| self.consent(request));
+        Framed::new(&mut stream, id).send(Kind::Accept, &[check.is_ok() as u8])?;
+        check?;
+        self.session(id, request, stream)
+    }
+
+    fn session(&self, id: u32, request: Request, mut stream: Substream) -> Result<Reply> {
+        match request {
+            Request::Keygen => {
+                let key = twopc::keygen(&mut stream)?;
+                let pubkey = key.aggregate_pubkey;
+                lock(&self.keys).insert(id, key);
+                Ok(Reply::Key { id, pubkey })
+            }
+            Request::Nonce => {
+                let nonce =
+                    nonce::run(background_inverse, self.me, std::slice::from_mut(&mut stream))?;
+                let point = nonce.point;
+                lock(&self.nonces).insert(id, nonce);
+                Ok(Reply::Nonce { id, point })
+            }
+            Request::Sign { key, nonce: None, m } => {
+                let key = self.key(key)?;
+                twopc::sign(&key, background_inverse, &m, stream).map(Reply::Signature)
+            }
+            Request::Sign {
+                key,
+                nonce: Some(nonce),
+                m,
+            } => {
+                let key = self.key(key)?;
+                // A nonce signs once, whether or not the session succeeds
+                let nonce = lock(&self.nonces)
+                    .remove(&nonce)
+                    .ok_or(Error::ProtocolAbort("no such nonce"))?;
+                npc::sign(&nonce, &key, &m, self.me, std::slice::from_mut(&mut stream))
+                    .map(Reply::Signature)
+            }
+        }
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Config is what the daemon is started with, see cli::USAGE.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub peer: Peer,
+    /// where to bind the control socket
+    pub control: PathBuf,
+}
+
+/// serve_control answers commands on a Unix socket at path, one per line,
+/// each with a line `ok REPLY` or `error WHY`. A stale socket at path is
+/// replaced, but anything else there, including a socket some daemon still
+/// listens on, is left alone and we fail.
+pub fn serve_control(node: &Node, path: &Path) -> Result<()> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    for client in listener.incoming() {
+        let (node, client) = (node.clone(), client?);
+        thread::spawn(move || control_client(&node, client));
+    }
+    Ok(())
+}
+
+/// remove_stale_socket removes path if it is a socket nobody listens on,
+/// failing if anything else is there.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(Error::Config(format!("{}: exists and is not a socket", path.display())));
+    }
+    match UnixStream::connect(path) {
+        Ok(_) => Err(Error::Config(format!("{}: a daemon is already listening", path.display()))),
+        Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            Ok(std::fs::remove_file(path)?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn control_client(node: &Node, client: UnixStream) -> Result<()> {
+    let mut out = client.try_clone()?;
+    for line in BufReader::new(client).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse::<Command>().and_then(|command| node.command(command)) {
+            Ok(reply) => writeln!(out, "ok {}", reply)?,
+            Err(e) => writeln!(out, "error {}", e)?,
+        }
+    }
+    Ok(())
+}
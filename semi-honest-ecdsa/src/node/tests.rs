@@ -0,0 +1,234 @@
+use super::*;
+
+fn pair() -> (Node, Node) {
+    let (a, b) = UnixStream::pair().unwrap();
+    (Node::new(a, true).unwrap(), Node::new(b, false).unwrap())
+}
+
+fn verify(
+    pubkey: &secp256k1::PublicKey,
+    m: &crate::scalars::Scalar,
+    sig: &secp256k1::ecdsa::Signature,
+) {
+    let ctx = secp256k1::Secp256k1::new();
+    let msg = secp256k1::Message::from_digest_slice(&m.to_bytes()[..]).unwrap();
+    assert!(ctx.verify_ecdsa(&msg, sig, pubkey).is_ok());
+}
+
+/// retry calls f until it succeeds, failing with what and the last error if
+/// that takes more than about five seconds.
+fn retry<T, E, F>(what: &str, mut f: F) -> T
+where
+    E: std::fmt::Display,
+    F: FnMut() -> std::result::Result<T, E>,
+{
+    let mut tries = 0;
+    loop {
+        match f() {
+            Ok(t) => return t,
+            Err(e) => {
+                tries += 1;
+                assert!(tries < 500, "{}: {}", what, e);
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+        }
+    }
+}
+
+#[test]
+fn either_node_starts_sessions() {
+    let (a, b) = pair();
+    let (id, pubkey) = match a.run(Request::Keygen).unwrap() {
+        Reply::Key { id, pubkey } => (id, pubkey),
+        r => panic!("keygen replied {}", r),
+    };
+    // b learns the key as it finishes its side, which may be after a does
+    let m = crate::scalars::Scalar::random();
+    a.approve(Request::Sign { key: id, nonce: None, m });
+    let sign = || b.run(Request::Sign { key: id, nonce: None, m });
+    let sig = match retry("b never signed", sign) {
+        Reply::Signature(sig) => sig,
+        r => panic!("sign replied {}", r),
+    };
+    verify(&pubkey, &m, &sig);
+}
+
+#[test]
+fn peer_refuses_unknown_keys() {
+    let (a, _b) = pair();
+    let m = crate::scalars::Scalar::random();
+    assert!(a.run(Request::Sign { key: 7, nonce: None, m }).is_err());
+}
+
+#[test]
+fn nonces_sign_once() {
+    let (a, b) = pair();
+    let (key, pubkey) = match a.run(Request::Keygen).unwrap() {
+        Reply::Key { id, pubkey } => (id, pubkey),
+        r => panic!("keygen replied {}", r),
+    };
+    let nonce = match b.run(Request::Nonce).unwrap() {
+        Reply::Nonce { id, .. } => id,
+        r => panic!("nonce replied {}", r),
+    };
+    let m = crate::scalars::Scalar::random();
+    let sign = Request::Sign {
+        key,
+        nonce: Some(nonce),
+        m,
+    };
+    b.approve(sign);
+    // a may not have stored the nonce yet
+    let sig = match retry("a never signed", || a.run(sign)) {
+        Reply::Signature(sig) => sig,
+        r => panic!("sign replied {}", r),
+    };
+    verify(&pubkey, &m, &sig);
+    assert!(a.run(sign).is_err());
+}
+
+#[test]
+fn peer_needs_approval_to_sign() {
+    let (a, b) = pair();
+    let (key, pubkey) = match a.run(Request::Keygen).unwrap() {
+        Reply::Key { id, pubkey } => (id, pubkey),
+        r => panic!("keygen replied {}", r),
+    };
+    retry("b never stored the key", || b.key(key));
+    let m = crate::scalars::Scalar::random();
+    let sign = Request::Sign { key, nonce: None, m };
+    assert!(b.run(sign).is_err());
+    a.approve(Request::Sign {
+        key,
+        nonce: None,
+        m: crate::scalars::Scalar::random(),
+    });
+    assert!(b.run(sign).is_err());
+    a.approve(sign);
+    match b.run(sign).unwrap() {
+        Reply::Signature(sig) => verify(&pubkey, &m, &sig),
+        r => panic!("sign replied {}", r),
+    }
+    // an approval is good for one session
+    assert!(b.run(sign).is_err());
+}
+
+#[test]
+fn unparsable_requests_are_refused() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let _node = Node::new(a, true).unwrap();
+    let mux = Mux::new(b).unwrap();
+    let mut stream = mux.open(4);
+    let mut request = Request::Keygen.to_bytes(4);
+    request[0] = 0xff;
+    Framed::new(mux.open(REQUESTS), REQUESTS)
+        .send(Kind::Request, &request[..])
+        .unwrap();
+    let (done, answer) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let mut accepted = [1u8; 1];
+        let got = Framed::new(&mut stream, 4).recv(Kind::Accept, &mut accepted[..]);
+        let _ = done.send(got.map(|()| accepted[0]));
+    });
+    let accepted = answer.recv_timeout(std::time::Duration::from_secs(5));
+    assert_eq!(accepted.expect("no answer to an unparsable request").unwrap(), 0);
+}
+
+#[test]
+fn request_round_trip() {
+    let m = crate::scalars::Scalar::random();
+    let requests = [
+        Request::Keygen,
+        Request::Nonce,
+        Request::Sign { key: 3, nonce: None, m },
+        Request::Sign {
+            key: 3,
+            nonce: Some(5),
+            m,
+        },
+    ];
+    for request in requests.iter() {
+        assert_eq!(Request::from_bytes(&request.to_bytes(9)).unwrap(), (9, *request));
+    }
+    let line = format!("sign 3 {} 5", to_hex(&m.to_bytes()));
+    assert_eq!(line.parse::<Command>().unwrap(), Command::Run(requests[3]));
+    let line = format!("approve 3 {}", to_hex(&m.to_bytes()));
+    assert_eq!(line.parse::<Command>().unwrap(), Command::Approve(requests[2]));
+    assert_eq!("peers".parse::<Command>().unwrap(), Command::Peers);
+    assert!("sign 3 00".parse::<Command>().is_err());
+    assert!("verify".parse::<Command>().is_err());
+}
+
+#[test]
+fn reply_round_trip() {
+    let ctx = secp256k1::Secp256k1::new();
+    let pubkey = crate::scalars::Scalar::random().base_point(&ctx).unwrap();
+    let replies = [
+        Reply::Key { id: 1, pubkey },
+        Reply::Nonce { id: 2, point: pubkey },
+        Reply::Peer {
+            peer: Some("connect:127.0.0.1:7000".parse().unwrap()),
+            up: true,
+        },
+        Reply::Peer {
+            peer: None,
+            up: false,
+        },
+        Reply::Approved,
+    ];
+    for reply in replies.iter() {
+        assert_eq!(&reply.to_string().parse::<Reply>().unwrap(), reply);
+    }
+    assert_eq!(
+        replies[2].to_json(),
+        "{\"peer\":\"connect:127.0.0.1:7000\",\"up\":true}"
+    );
+}
+
+#[test]
+fn control_socket() {
+    let path = std::env::temp_dir().join(format!("semi-honest-ecdsa-{}.sock", std::process::id()));
+    let (a, _b) = pair();
+    {
+        let path = path.clone();
+        thread::spawn(move || serve_control(&a, &path));
+    }
+    let client = retry("control socket never listened", || UnixStream::connect(&path));
+    let mut out = client.try_clone().unwrap();
+    let mut lines = BufReader::new(client).lines();
+    writeln!(out, "keygen").unwrap();
+    let reply = lines.next().unwrap().unwrap();
+    assert!(reply.starts_with("ok key "), "{}", reply);
+    writeln!(out, "sign nonsense").unwrap();
+    let reply = lines.next().unwrap().unwrap();
+    assert!(reply.starts_with("error "), "{}", reply);
+    writeln!(out, "approve 1 {}", to_hex(&[7u8; 32])).unwrap();
+    assert_eq!(lines.next().unwrap().unwrap(), "ok approved");
+    writeln!(out, "peers").unwrap();
+    assert_eq!(lines.next().unwrap().unwrap(), "ok peer - up");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn control_socket_path_is_checked() {
+    let dir = std::env::temp_dir();
+    let (a, _b) = pair();
+    // a file that is not a socket is left alone
+    let file = dir.join(format!("semi-honest-ecdsa-{}.file", std::process::id()));
+    std::fs::write(&file, b"keep").unwrap();
+    assert!(serve_control(&a, &file).is_err());
+    assert_eq!(std::fs::read(&file).unwrap(), b"keep");
+    let _ = std::fs::remove_file(&file);
+    // as is a socket some daemon listens on
+    let live = dir.join(format!("semi-honest-ecdsa-{}.live", std::process::id()));
+    let _ = std::fs::remove_file(&live);
+    let _listener = UnixListener::bind(&live).unwrap();
+    assert!(serve_control(&a, &live).is_err());
+    let _ = std::fs::remove_file(&live);
+    // but a stale one is replaced
+    let stale = dir.join(format!("semi-honest-ecdsa-{}.stale", std::process::id()));
+    let _ = std::fs::remove_file(&stale);
+    drop(UnixListener::bind(&stale).unwrap());
+    assert!(remove_stale_socket(&stale).is_ok());
+    assert!(!stale.exists());
+}
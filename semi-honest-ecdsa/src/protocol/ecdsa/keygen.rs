@@ -0,0 +1,172 @@
+use super::util::{exchange, peer_slot, SETUP_SESSION};
+use crate::error::{Error, Result};
+use crate::scalars::Scalar;
+use crate::util::{HasTryClone, ReadWrite};
+use crate::wire::Kind;
+pub use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+/// KeyAggregation selects how participants' public keys are combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyAggregation {
+    /// B = sum h_i beta_i with h_i = H(L || beta_i), needing no extra round.
+    MuSig,
+    /// B = sum beta_i, with every beta_i committed to before any is
+    /// revealed so no participant can cancel out another's key.
+    Committed,
+}
+
+/// A KeyShare is one participant's share of an aggregate key.
+///
+/// It is produced ahead of signing so the aggregate key can be published
+/// (e.g. paid to) before any signature under it exists. Never sign twice
+/// under one KeyShare with the same nonce. Both secrets are wiped when it
+/// drops.
+#[derive(Clone)]
+pub struct KeyShare {
+    /// our secret key alpha_i
+    pub secret: Zeroizing<Scalar>,
+    /// the share of the aggregate secret we sign with, alpha_i h_i under
+    /// MuSig and alpha_i otherwise
+    pub tweaked_secret: Zeroizing<Scalar>,
+    pub aggregate_pubkey: secp256k1::PublicKey,
+    /// every participant's beta_i in participant order
+    pub participants: Vec<secp256k1::PublicKey>,
+}
+
+pub(crate) fn musig_coefficient(lx: &Sha256, k: &secp256k1::PublicKey) -> Scalar {
+    let h = lx.clone().chain_update(&k.serialize()[..]).finalize();
+    let mut z = [0u8; 32];
+    z.clone_from_slice(h.as_slice());
+    Scalar::from_bytes(&z)
+}
+
+/// aggregate combines keys, where keys[me] is the public key for `key`.
+pub(crate) fn aggregate<C: secp256k1::Verification>(
+    ctx: &secp256k1::Secp256k1<C>,
+    mode: KeyAggregation,
+    keys: Vec<secp256k1::PublicKey>,
+    me: usize,
+    key: &Scalar,
+) -> Result<KeyShare> {
+    for (j, k) in keys.iter().enumerate() {
+        if keys[..j].contains(k) {
+            return Err(Error::ProtocolAbort("duplicate participant key"));
+        }
+    }
+    let (my_share, lx) = match mode {
+        KeyAggregation::MuSig => {
+            let l = keys
+                .iter()
+                .fold(Sha256::new(), |h, k| h.chain_update(&k.serialize()[..]))
+                .finalize();
+            let lx = Sha256::new().chain_update(l.as_slice());
+            (Zeroizing::new(musig_coefficient(&lx, &keys[me]) * *key), Some(lx))
+        }
+        KeyAggregation::Committed => (Zeroizing::new(*key), None),
+    };
+    let mut acc: Option<secp256k1::PublicKey> = None;
+    for k in keys.iter() {
+        let mut k = *k;
+        if let Some(ref lx) = lx {
+            k = musig_coefficient(lx, &k).mul_point(ctx, &k)?;
+        }
+        acc = Some(match acc {
+            Some(acc) => acc.combine(&k)?,
+            None => k,
+        });
+    }
+    Ok(KeyShare {
+        secret: Zeroizing::new(*key),
+        tweaked_secret: my_share,
+        aggregate_pubkey: acc.ok_or(Error::ProtocolAbort("no participants"))?,
+        participants: keys,
+    })
+}
+
+/// run generates a fresh single use key with every peer. See npc::run for
+/// the layout of `peers`.
+pub fn run<T>(mode: KeyAggregation, me: usize, peers: &mut [T]) -> Result<KeyShare>
+where
+    T: 'static + ReadWrite + HasTryClone,
+{
+    Ok(run_batch(mode, 1, me, peers)?.remove(0))
+}
+
+/// run_batch generates `count` fresh single use keys with one exchange.
+pub fn run_batch<T>(
+    mode: KeyAggregation,
+    count: usize,
+    me: usize,
+    peers: &mut [T],
+) -> Result<Vec<KeyShare>>
+where
+    T: 'static + ReadWrite + HasTryClone,
+{
+    let keys: Zeroizing<Vec<Scalar>> =
+        Zeroizing::new((0..count).map(|_| Scalar::random()).collect());
+    aggregate_batch(mode, &keys[..], me, peers)
+}
+
+/// aggregate_batch exchanges the public keys for a batch of secret keys with
+/// every peer and aggregates each one.
+///
+/// In Committed mode a round of SHA256(beta_i) commitments precedes the
+/// reveal, and a reveal not matching its commitment aborts.
+pub fn aggregate_batch<T>(
+    mode: KeyAggregation,
+    keys: &[Scalar],
+    me: usize,
+    peers: &mut [T],
+) -> Result<Vec<KeyShare>>
+where
+    T: 'static + ReadWrite + HasTryClone,
+{
+    let n = peers.len() + 1;
+    if me >= n || n < 2 {
+        return Err(Error::ProtocolAbort("bad participant index"));
+    }
+    let ctx = &secp256k1::Secp256k1::new();
+    let mut my_pks = Vec::with_capacity(keys.len());
+    let mut b33s = Vec::with_capacity(keys.len() * 33);
+    let mut commitments = Vec::with_capacity(keys.len() * 32);
+    for key in keys.iter() {
+        let pk = key.base_point(ctx)?;
+        b33s.extend_from_slice(&pk.serialize()[..]);
+        commitments.extend_from_slice(
+            Sha256::new()
+                .chain_update(&pk.serialize()[..])
+                .finalize()
+                .as_slice(),
+        );
+        my_pks.push(pk);
+    }
+    let peer_commitments = if mode == KeyAggregation::Committed {
+        Some(exchange(peers, Kind::Commitment, SETUP_SESSION, &commitments[..])?)
+    } else {
+        None
+    };
+    let peer_pks = exchange(peers, Kind::PublicKey, SETUP_SESSION, &b33s[..])?;
+    let mut aggregates = Vec::with_capacity(keys.len());
+    for (p, key) in keys.iter().enumerate() {
+        let mut all = Vec::with_capacity(n);
+        for j in 0..n {
+            if j == me {
+                all.push(my_pks[p]);
+                continue;
+            }
+            let b33 = &peer_pks[peer_slot(me, j)][p * 33..(p + 1) * 33];
+            if let Some(ref peer_commitments) = peer_commitments {
+                let commitment = &peer_commitments[peer_slot(me, j)][p * 32..(p + 1) * 32];
+                if Sha256::new().chain_update(b33).finalize().as_slice() != commitment {
+                    return Err(Error::PeerEquivocation);
+                }
+            }
+            all.push(
+                secp256k1::PublicKey::from_slice(b33).map_err(|_| Error::InvalidPoint)?,
+            );
+        }
+        aggregates.push(aggregate(ctx, mode, all, me, key)?);
+    }
+    Ok(aggregates)
+}
@@ -1,4 +1,7 @@
+pub mod keygen;
+pub mod nonce;
+pub mod npc;
 #[cfg(test)]
 mod tests;
 pub mod twopc;
-mod util;
+pub mod util;
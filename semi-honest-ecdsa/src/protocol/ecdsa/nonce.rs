@@ -0,0 +1,122 @@
+use super::util::{exchange, peer_slot, SETUP_SESSION};
+use crate::error::{Error, Result};
+use crate::scalars::Scalar;
+use crate::util::ReadWrite;
+use crate::wire::{Framed, Kind};
+use zeroize::Zeroizing;
+
+/// A NonceShare is one participant's view of a reusable nonce K.
+///
+/// Every participant learns the same K*G (and so the same r) while keeping
+/// q = k_i^{-1} to itself, so that K^{-1} = q_0 ... q_{n-1}. q is wiped
+/// when the share drops.
+#[derive(Clone)]
+pub struct NonceShare {
+    pub point: secp256k1::PublicKey,
+    pub r: Scalar,
+    pub q: Zeroizing<Scalar>,
+}
+
+/// run computes K*G by passing a point around the ring of participants,
+/// each multiplying by their own k_i.
+///
+/// Participant 0 starts the ring from G and the last participant announces
+/// K*G to everyone else. See npc::run for the layout of `peers`.
+pub fn run<T, Inv>(get_inverse: Inv, me: usize, peers: &mut [T]) -> Result<NonceShare>
+where
+    T: ReadWrite,
+    Inv: FnOnce() -> super::util::Inverse,
+{
+    let n = peers.len() + 1;
+    if me >= n || n < 2 {
+        return Err(Error::ProtocolAbort("bad participant index"));
+    }
+    let inverse = get_inverse();
+    let ctx = &secp256k1::Secp256k1::new();
+    let k_g = if me == 0 {
+        inverse.0.base_point(ctx)?
+    } else {
+        let mut b33 = [0u8; 33];
+        Framed::new(&mut peers[peer_slot(me, me - 1)], SETUP_SESSION)
+            .recv(Kind::NoncePoint, &mut b33[..])?;
+        let k_g = secp256k1::PublicKey::from_slice(&b33).map_err(|_| Error::InvalidPoint)?;
+        inverse.0.mul_point(ctx, &k_g)?
+    };
+    let point = if me == n - 1 {
+        for peer in peers.iter_mut() {
+            Framed::new(peer, SETUP_SESSION).send(Kind::NoncePoint, &k_g.serialize()[..])?;
+        }
+        k_g
+    } else {
+        Framed::new(&mut peers[peer_slot(me, me + 1)], SETUP_SESSION)
+            .send(Kind::NoncePoint, &k_g.serialize()[..])?;
+        let mut b33 = [0u8; 33];
+        Framed::new(&mut peers[peer_slot(me, n - 1)], SETUP_SESSION)
+            .recv(Kind::NoncePoint, &mut b33[..])?;
+        secp256k1::PublicKey::from_slice(&b33).map_err(|_| Error::InvalidPoint)?
+    };
+    let mut xb = [0; 32];
+    xb[..].clone_from_slice(&point.serialize()[1..]);
+    Ok(NonceShare {
+        point,
+        r: Scalar::from_bytes(&xb),
+        q: super::util::joined_inverse(inverse.1)?,
+    })
+}
+
+impl NonceShare {
+    /// rerandomize derives the nonce k'K from a tweak k' every participant
+    /// agreed on.
+    ///
+    /// All participants scale K*G by k', and participant 0 alone scales its
+    /// q by q' = k'^{-1}, so the shares still multiply out to (k'K)^{-1}.
+    pub fn rerandomize(&self, tweak: &Scalar, me: usize) -> Result<NonceShare> {
+        let ctx = &secp256k1::Secp256k1::new();
+        let point = tweak.mul_point(ctx, &self.point)?;
+        let mut xb = [0; 32];
+        xb[..].clone_from_slice(&point.serialize()[1..]);
+        let q = if me == 0 {
+            Zeroizing::new(*self.q * tweak.inv())
+        } else {
+            self.q.clone()
+        };
+        Ok(NonceShare {
+            point,
+            r: Scalar::from_bytes(&xb),
+            q,
+        })
+    }
+}
+
+use crate::util::HasTryClone;
+pub use sha2::{Digest, Sha256};
+/// coin_flip jointly picks a random tweak by committing to a random value,
+/// then revealing it once every commitment is in.
+///
+/// The tweak is the hash of all reveals in participant order, so no
+/// participant can bias it after seeing the others.
+pub fn coin_flip<T>(me: usize, peers: &mut [T]) -> Result<Scalar>
+where
+    T: 'static + ReadWrite + HasTryClone,
+{
+    let coin: [u8; 32] = rand::random();
+    let commitment = Sha256::new().chain_update(&coin[..]).finalize();
+    let commitments = exchange(peers, Kind::Commitment, SETUP_SESSION, commitment.as_slice())?;
+    let coins = exchange(peers, Kind::Reveal, SETUP_SESSION, &coin[..])?;
+    let mut h = Sha256::new();
+    for j in 0..peers.len() + 1 {
+        if j == me {
+            h = h.chain_update(&coin[..]);
+            continue;
+        }
+        let slot = peer_slot(me, j);
+        let reveal = Sha256::new().chain_update(&coins[slot][..]).finalize();
+        if reveal.as_slice() != &commitments[slot][..] {
+            return Err(Error::PeerEquivocation);
+        }
+        h = h.chain_update(&coins[slot][..]);
+    }
+    let mut z = [0u8; 32];
+    z.clone_from_slice(h.finalize().as_slice());
+    Ok(Scalar::from_bytes(&z))
+}
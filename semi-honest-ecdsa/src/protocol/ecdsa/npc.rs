@@ -0,0 +1,191 @@
+use super::keygen::{KeyAggregation, KeyShare};
+use super::nonce::NonceShare;
+use super::util::peer_slot;
+use crate::error::{joined, Error, Result};
+use crate::protocol::mult::Radix;
+use crate::scalars::Scalar;
+use crate::util::{HasTryClone, ReadWrite};
+use crate::wire::{Framed, Kind};
+use std::thread;
+
+/// run signs m as participant `me` of `peers.len() + 1` with a fresh key
+/// and nonce.
+///
+/// peers[j] is the connection to participant j (or j + 1 for j >= me).
+pub fn run<T, Inv>(
+    get_inverse: Inv,
+    m: &Scalar,
+    mode: KeyAggregation,
+    me: usize,
+    mut peers: Vec<T>,
+) -> Result<secp256k1::ecdsa::Signature>
+where
+    T: 'static + ReadWrite + HasTryClone,
+    Inv: FnOnce() -> super::util::Inverse,
+{
+    let key = super::keygen::run(mode, me, &mut peers[..])?;
+    let nonce = super::nonce::run(get_inverse, me, &mut peers[..])?;
+    sign(&nonce, &key, m, me, &mut peers[..])
+}
+
+/// sign signs m under a previously generated key and nonce.
+pub fn sign<T>(
+    nonce: &NonceShare,
+    key: &KeyShare,
+    m: &Scalar,
+    me: usize,
+    peers: &mut [T],
+) -> Result<secp256k1::ecdsa::Signature>
+where
+    T: 'static + ReadWrite + HasTryClone,
+{
+    sign_batch(
+        nonce,
+        std::slice::from_ref(key),
+        std::slice::from_ref(m),
+        me,
+        peers,
+    )?
+    .remove(0)
+}
+
+/// sign_batch signs messages[p] under keys[p], all with the same nonce.
+///
+/// Final shares are exchanged once for the whole batch, and each round runs
+/// its multiplications with a peer as one batch of OTs, with every peer at
+/// once, so the round trips per batch do not grow with the number of
+/// messages.
+/// Participant 0 folds each message into its key term. Returns an Error if
+/// the session broke down, otherwise one entry per message which is an
+/// Error if that signature failed to verify.
+pub fn sign_batch<T>(
+    nonce: &NonceShare,
+    keys: &[KeyShare],
+    messages: &[Scalar],
+    me: usize,
+    peers: &mut [T],
+) -> Result<Vec<Result<secp256k1::ecdsa::Signature>>>
+where
+    T: 'static + ReadWrite + HasTryClone,
+{
+    let n = peers.len() + 1;
+    if me >= n || n < 2 {
+        return Err(Error::ProtocolAbort("bad participant index"));
+    }
+    if keys.len() != messages.len() {
+        return Err(Error::ProtocolAbort("one key is needed per message"));
+    }
+    if keys.iter().any(|key| key.participants.len() != n) {
+        return Err(Error::ProtocolAbort("key is not shared with these peers"));
+    }
+    let count = messages.len();
+    let ctx = &secp256k1::Secp256k1::new();
+
+    let r = nonce.r;
+    // We have, per message,
+    // q_0 ... q_{n-1} (eta_0 + ... + eta_{n-1})
+    // where eta_0 = M + r a_0 and eta_i = r a_i
+    //
+    // In round j every participant holds an additive share of the running
+    // sum, and participant j distributes q_j over it: its own term directly,
+    // everyone else's through OT*(q_j, eta_i). Sender shares do not depend on
+    // the input, so only participant j waits on the round.
+    let i_nonce = &nonce.q;
+    let mut gammas: Vec<Scalar> = keys
+        .iter()
+        .zip(messages.iter())
+        .map(|(key, m)| {
+            let mut gamma = *key.tweaked_secret * r;
+            if me == 0 {
+                gamma += *m;
+            }
+            gamma
+        })
+        .collect();
+    for j in 0..n {
+        let session = session(nonce, keys, messages, j);
+        if j == me {
+            // one batch of multiplications per peer, all under way at once
+            let mut requests = Vec::with_capacity(peers.len());
+            for peer in peers.iter() {
+                let peer = Framed::new(peer.try_clone()?, session);
+                let i_nonce = i_nonce.clone();
+                requests.push(thread::spawn(move || -> Result<Vec<Scalar>> {
+                    crate::protocol::mult::receiver::run_scale_free_batch(
+                        &i_nonce,
+                        count,
+                        Radix::default(),
+                        peer,
+                    )
+                }));
+            }
+            for gamma in gammas.iter_mut() {
+                *gamma *= **i_nonce;
+            }
+            for request in requests {
+                for (gamma, share) in gammas.iter_mut().zip(joined(request)?.iter()) {
+                    *gamma += *share;
+                }
+            }
+        } else {
+            let peer = &peers[peer_slot(me, j)];
+            gammas = crate::protocol::mult::sender::run_scale_free_batch(
+                &gammas[..],
+                Radix::default(),
+                Framed::new(peer.try_clone()?, session),
+            )?;
+        }
+    }
+
+    // Reveal Gamma_i and sum
+    let mut b32s = Vec::with_capacity(count * 32);
+    for gamma in gammas.iter() {
+        b32s.extend_from_slice(&gamma.to_bytes()[..]);
+    }
+    let session = session(nonce, keys, messages, n);
+    let peer_gammas = super::util::exchange(peers, Kind::Share, session, &b32s[..])?;
+    let mut sigs = Vec::with_capacity(count);
+    for (p, (s, m)) in gammas.iter_mut().zip(messages.iter()).enumerate() {
+        for peer_gamma in peer_gammas.iter() {
+            let mut buf = [0; 32];
+            buf.clone_from_slice(&peer_gamma[p * 32..(p + 1) * 32]);
+            *s += Scalar::from_bytes(&buf);
+        }
+        sigs.push(finalize(ctx, &r, s, m, &keys[p].aggregate_pubkey));
+    }
+    Ok(sigs)
+}
+
+/// session frames round j of signing messages under keys with nonce, so no
+/// frame of one round, batch or message can be taken for another's.
+fn session(nonce: &NonceShare, keys: &[KeyShare], messages: &[Scalar], j: usize) -> u32 {
+    let mut b = Vec::with_capacity(37 + messages.len() * 69);
+    b.extend_from_slice(&(j as u32).to_be_bytes());
+    b.extend_from_slice(&nonce.point.serialize()[..]);
+    for (p, (key, m)) in keys.iter().zip(messages.iter()).enumerate() {
+        b.extend_from_slice(&(p as u32).to_be_bytes());
+        b.extend_from_slice(&key.aggregate_pubkey.serialize()[..]);
+        b.extend_from_slice(&m.to_bytes()[..]);
+    }
+    crate::wire::session_id(&[&b[..]])
+}
+
+fn finalize<C: secp256k1::Verification>(
+    ctx: &secp256k1::Secp256k1<C>,
+    r: &Scalar,
+    s: &Scalar,
+    m: &Scalar,
+    our_key: &secp256k1::PublicKey,
+) -> Result<secp256k1::ecdsa::Signature> {
+    let mut x = [0; 64];
+    x[0..=31].clone_from_slice(&r.to_bytes()[..]);
+    x[32..].clone_from_slice(&s.to_bytes()[..]);
+
+    let mut sig = secp256k1::ecdsa::Signature::from_compact(&x[..])?;
+    sig.normalize_s();
+
+    let msg = secp256k1::Message::from_digest_slice(&m.to_bytes()[..])?;
+    ctx.verify_ecdsa(&msg, &sig, our_key)
+        .map_err(|_| Error::VerificationFailed)?;
+    Ok(sig)
+}
@@ -1,37 +1,484 @@
 use crate::*;
+use super::keygen::KeyAggregation;
 
-use crate::test::Bencher;
 fn test_2pc_sig() {
     use std::os::unix::net::UnixStream;
     let (a, b) = UnixStream::pair().unwrap();
-    let m = scalars::random_scalar();
+    let m = scalars::Scalar::random();
     std::thread::spawn(move || {
-        protocol::ecdsa::twopc::run(super::util::background_inverse, &m, a);
+        protocol::ecdsa::twopc::run(super::util::background_inverse, &m, a).unwrap();
     });
-    assert!(protocol::ecdsa::twopc::run(super::util::background_inverse, &m, b).is_some());
+    assert!(protocol::ecdsa::twopc::run(super::util::background_inverse, &m, b).is_ok());
 }
 #[test]
 fn do_test() {
     test_2pc_sig();
 }
 
-fn test_2pc_sig_inv(inv1: super::util::Inverse, inv2: super::util::Inverse) {
+/// mesh connects every pair of n participants, returning each participant's
+/// peers in index order with itself left out.
+#[allow(clippy::needless_range_loop)]
+fn mesh(n: usize) -> Vec<Vec<std::os::unix::net::UnixStream>> {
     use std::os::unix::net::UnixStream;
-    let (a, b) = UnixStream::pair().unwrap();
-    let m = scalars::random_scalar();
-    std::thread::spawn(move || {
-        protocol::ecdsa::twopc::run(|| inv1, &m, a);
+    let mut slots: Vec<Vec<Option<UnixStream>>> =
+        (0..n).map(|_| (0..n).map(|_| None).collect()).collect();
+    for i in 0..n {
+        for j in i + 1..n {
+            let (a, b) = UnixStream::pair().unwrap();
+            slots[i][j] = Some(a);
+            slots[j][i] = Some(b);
+        }
+    }
+    slots
+        .into_iter()
+        .map(|row| row.into_iter().flatten().collect())
+        .collect()
+}
+
+fn test_npc_sig(n: usize, mode: KeyAggregation) {
+    let m = scalars::Scalar::random();
+    let handles: Vec<_> = mesh(n)
+        .into_iter()
+        .enumerate()
+        .map(|(me, peers)| {
+            std::thread::spawn(move || {
+                protocol::ecdsa::npc::run(
+                    super::util::background_inverse,
+                    &m,
+                    mode,
+                    me,
+                    peers,
+                )
+            })
+        })
+        .collect();
+    let sigs: Vec<_> = handles.into_iter().map(|h| h.join().unwrap().ok()).collect();
+    assert!(sigs[0].is_some());
+    assert!(sigs.iter().all(|s| *s == sigs[0]));
+}
+#[test]
+fn npc_two_party() {
+    test_npc_sig(2, KeyAggregation::MuSig);
+}
+#[test]
+fn npc_many_party() {
+    test_npc_sig(4, KeyAggregation::MuSig);
+}
+
+#[test]
+fn nonce_ring() {
+    let handles: Vec<_> = mesh(3)
+        .into_iter()
+        .enumerate()
+        .map(|(me, mut peers)| {
+            std::thread::spawn(move || {
+                protocol::ecdsa::nonce::run(super::util::background_inverse, me, &mut peers[..])
+            })
+        })
+        .collect();
+    let shares: Vec<_> = handles
+        .into_iter()
+        .map(|h| h.join().unwrap().unwrap())
+        .collect();
+    let q = shares.iter().skip(1).fold(*shares[0].q, |acc, share| acc * *share.q);
+    let ctx = &secp256k1::Secp256k1::new();
+    let k_g = q.inv().base_point(ctx).unwrap();
+    for share in shares.iter() {
+        assert_eq!(share.point, k_g);
+        assert_eq!(share.r, shares[0].r);
+    }
+}
+
+#[test]
+fn exchange_is_framed() {
+    use crate::wire::{Kind, MAX_PAYLOAD};
+    use protocol::ecdsa::util::{exchange, SETUP_SESSION};
+    let (a, b) = std::os::unix::net::UnixStream::pair().unwrap();
+    let (mut a, mut b) = ([a], [b]);
+    // more than fits in one frame
+    let msg: Vec<u8> = (0..MAX_PAYLOAD * 2 + 5).map(|i| i as u8).collect();
+    let echo = msg.clone();
+    let h = std::thread::spawn(move || exchange(&mut b[..], Kind::Share, 7, &echo[..]));
+    assert_eq!(exchange(&mut a[..], Kind::Share, 7, &msg[..]).unwrap(), vec![msg.clone()]);
+    assert_eq!(h.join().unwrap().unwrap(), vec![msg]);
+
+    // a peer a step ahead is caught rather than read as this step's bytes
+    let (a, b) = std::os::unix::net::UnixStream::pair().unwrap();
+    let (mut a, mut b) = ([a], [b]);
+    let h = std::thread::spawn(move || {
+        exchange(&mut b[..], Kind::PublicKey, SETUP_SESSION, &[2u8; 33])
     });
-    assert!(protocol::ecdsa::twopc::run(|| inv2, &m, b).is_some());
+    assert!(exchange(&mut a[..], Kind::Commitment, SETUP_SESSION, &[0u8; 33]).is_err());
+    assert!(h.join().unwrap().is_err());
+}
+
+#[test]
+fn nonce_reused_across_signatures() {
+    let handles: Vec<_> = mesh(3)
+        .into_iter()
+        .enumerate()
+        .map(|(me, mut peers)| {
+            std::thread::spawn(move || {
+                let nonce = protocol::ecdsa::nonce::run(
+                    super::util::background_inverse,
+                    me,
+                    &mut peers[..],
+                )
+                .unwrap();
+                (0..2)
+                    .map(|i| {
+                        let m = scalars::Scalar::from_u64(i + 1);
+                        let key = protocol::ecdsa::keygen::run(
+                            KeyAggregation::MuSig,
+                            me,
+                            &mut peers[..],
+                        )
+                        .unwrap();
+                        protocol::ecdsa::npc::sign(&nonce, &key, &m, me, &mut peers[..])
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for h in handles {
+        assert!(h.join().unwrap().iter().all(|sig| sig.is_ok()));
+    }
+}
+
+#[test]
+fn npc_batch() {
+    let messages: Vec<_> = (0..4).map(|_| scalars::Scalar::random()).collect();
+    let handles: Vec<_> = mesh(3)
+        .into_iter()
+        .enumerate()
+        .map(|(me, mut peers)| {
+            let messages = messages.clone();
+            std::thread::spawn(move || {
+                let nonce = protocol::ecdsa::nonce::run(
+                    super::util::background_inverse,
+                    me,
+                    &mut peers[..],
+                )
+                .unwrap();
+                let keys = protocol::ecdsa::keygen::run_batch(
+                    KeyAggregation::MuSig,
+                    messages.len(),
+                    me,
+                    &mut peers[..],
+                )
+                .unwrap();
+                protocol::ecdsa::npc::sign_batch(
+                    &nonce,
+                    &keys[..],
+                    &messages[..],
+                    me,
+                    &mut peers[..],
+                )
+            })
+        })
+        .collect();
+    let batches: Vec<_> = handles
+        .into_iter()
+        .map(|h| {
+            h.join()
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .map(|sig| sig.ok())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    for batch in batches.iter() {
+        assert_eq!(batch.len(), messages.len());
+        assert!(batch.iter().all(|sig| sig.is_some()));
+        assert_eq!(batch, &batches[0]);
+    }
+}
+
+/// Turns wraps a connection, counting the times a thread goes from writing
+/// to reading it, which is once per round trip it waits on. Writes from
+/// other threads, e.g. exchange's background writers, do not count.
+struct Turns {
+    inner: std::os::unix::net::UnixStream,
+    state: std::sync::Arc<std::sync::Mutex<(Vec<std::thread::ThreadId>, usize)>>,
+}
+
+impl std::io::Read for Turns {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let me = std::thread::current().id();
+        if let Some(at) = state.0.iter().position(|t| *t == me) {
+            state.0.remove(at);
+            state.1 += 1;
+        }
+        drop(state);
+        self.inner.read(buf)
+    }
+}
+
+impl std::io::Write for Turns {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let me = std::thread::current().id();
+        if !state.0.contains(&me) {
+            state.0.push(me);
+        }
+        drop(state);
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
 }
-#[bench]
-fn do_bench(b: &mut Bencher) {
-    let bg_inv = super::util::background_inverse_service(100);
-    std::thread::sleep(std::time::Duration::from_secs(3));
-    b.iter(|| {
-        let inv1 = bg_inv.iter().next().unwrap();
-        let inv2 = bg_inv.iter().next().unwrap();
 
-        test_2pc_sig_inv(inv1, inv2)
+impl ReadWrite for Turns {}
+
+impl HasTryClone for Turns {
+    fn try_clone(&self) -> std::io::Result<Turns> {
+        Ok(Turns {
+            inner: self.inner.try_clone()?,
+            state: self.state.clone(),
+        })
+    }
+}
+
+/// sign_turns is the most round trips any connection of three participants
+/// waits on to sign a batch of count messages.
+fn sign_turns(count: usize) -> usize {
+    let handles: Vec<_> = mesh(3)
+        .into_iter()
+        .enumerate()
+        .map(|(me, peers)| {
+            std::thread::spawn(move || {
+                let mut peers: Vec<Turns> = peers
+                    .into_iter()
+                    .map(|inner| Turns {
+                        inner,
+                        state: Default::default(),
+                    })
+                    .collect();
+                let nonce = protocol::ecdsa::nonce::run(
+                    super::util::background_inverse,
+                    me,
+                    &mut peers[..],
+                )
+                .unwrap();
+                let keys = protocol::ecdsa::keygen::run_batch(
+                    KeyAggregation::MuSig,
+                    count,
+                    me,
+                    &mut peers[..],
+                )
+                .unwrap();
+                let before: Vec<usize> = peers.iter().map(|p| p.state.lock().unwrap().1).collect();
+                let messages: Vec<_> = (1..=count as u64).map(scalars::Scalar::from_u64).collect();
+                let sigs = protocol::ecdsa::npc::sign_batch(
+                    &nonce,
+                    &keys[..],
+                    &messages[..],
+                    me,
+                    &mut peers[..],
+                )
+                .unwrap();
+                assert!(sigs.iter().all(|sig| sig.is_ok()));
+                peers
+                    .iter()
+                    .zip(before.iter())
+                    .map(|(p, b)| p.state.lock().unwrap().1 - b)
+                    .max()
+                    .unwrap()
+            })
+        })
+        .collect();
+    handles.into_iter().map(|h| h.join().unwrap()).max().unwrap()
+}
+
+#[test]
+fn npc_batch_round_trips() {
+    let turns = sign_turns(1);
+    assert!(turns > 0);
+    assert_eq!(turns, sign_turns(6));
+}
+
+#[test]
+fn npc_refuses_key_of_other_participants() {
+    use protocol::ecdsa::keygen::KeyShare;
+    use protocol::ecdsa::nonce::NonceShare;
+    let ctx = &secp256k1::Secp256k1::new();
+    let secret = scalars::Scalar::random();
+    let point = secret.base_point(ctx).unwrap();
+    let key = KeyShare {
+        secret: secret.into(),
+        tweaked_secret: secret.into(),
+        aggregate_pubkey: point,
+        participants: vec![point, point],
+    };
+    let nonce = NonceShare {
+        point,
+        r: scalars::Scalar::ONE,
+        q: secret.into(),
+    };
+    // three participants, but the key is shared by two
+    let mut peers = mesh(3).remove(0);
+    let m = scalars::Scalar::ONE;
+    assert!(protocol::ecdsa::npc::sign(&nonce, &key, &m, 0, &mut peers[..]).is_err());
+}
+
+#[test]
+fn rerandomized_nonce() {
+    // every participant must sign the same message
+    let m = scalars::Scalar::random();
+    let handles: Vec<_> = mesh(3)
+        .into_iter()
+        .enumerate()
+        .map(|(me, mut peers)| {
+            std::thread::spawn(move || {
+                let nonce = protocol::ecdsa::nonce::run(
+                    super::util::background_inverse,
+                    me,
+                    &mut peers[..],
+                )
+                .unwrap();
+                let tweak = protocol::ecdsa::nonce::coin_flip(me, &mut peers[..]).unwrap();
+                let derived = nonce.rerandomize(&tweak, me).unwrap();
+                let key =
+                    protocol::ecdsa::keygen::run(KeyAggregation::MuSig, me, &mut peers[..])
+                        .unwrap();
+                let sig = protocol::ecdsa::npc::sign(&derived, &key, &m, me, &mut peers[..]);
+                (nonce, derived, sig)
+            })
+        })
+        .collect();
+    let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    let q = results.iter().skip(1).fold(*results[0].1.q, |acc, share| acc * *share.1.q);
+    let ctx = &secp256k1::Secp256k1::new();
+    let k_g = q.inv().base_point(ctx).unwrap();
+    for (nonce, derived, sig) in results.iter() {
+        assert_eq!(derived.point, k_g);
+        assert!(derived.r != nonce.r);
+        assert!(sig.is_ok());
+    }
+}
+
+#[test]
+fn npc_committed_keys() {
+    test_npc_sig(3, KeyAggregation::Committed);
+}
+
+#[test]
+fn committed_keys_sum() {
+    let keys: Vec<_> = (0..3).map(|_| scalars::Scalar::random()).collect();
+    let handles: Vec<_> = mesh(3)
+        .into_iter()
+        .zip(keys.iter().cloned())
+        .enumerate()
+        .map(|(me, (mut peers, key))| {
+            std::thread::spawn(move || {
+                protocol::ecdsa::keygen::aggregate_batch(
+                    KeyAggregation::Committed,
+                    &[key],
+                    me,
+                    &mut peers[..],
+                )
+            })
+        })
+        .collect();
+    let sum: scalars::Scalar = keys.iter().sum();
+    let ctx = &secp256k1::Secp256k1::new();
+    let expected = sum.base_point(ctx).unwrap();
+    for (h, key) in handles.into_iter().zip(keys.iter()) {
+        let aggregates = h.join().unwrap().unwrap();
+        assert_eq!(*aggregates[0].secret, *key);
+        assert_eq!(*aggregates[0].tweaked_secret, *key);
+        assert_eq!(aggregates[0].aggregate_pubkey, expected);
+    }
+}
+
+#[test]
+fn key_share_signs_with_many_nonces() {
+    let messages: Vec<_> = (0..2).map(|_| scalars::Scalar::random()).collect();
+    let handles: Vec<_> = mesh(3)
+        .into_iter()
+        .enumerate()
+        .map(|(me, mut peers)| {
+            let messages = messages.clone();
+            std::thread::spawn(move || {
+                let key = protocol::ecdsa::keygen::run(KeyAggregation::MuSig, me, &mut peers[..])
+                    .unwrap();
+                let sigs: Vec<_> = messages
+                    .iter()
+                    .map(|m| {
+                        let nonce = protocol::ecdsa::nonce::run(
+                            super::util::background_inverse,
+                            me,
+                            &mut peers[..],
+                        )
+                        .unwrap();
+                        protocol::ecdsa::npc::sign(&nonce, &key, m, me, &mut peers[..])
+                    })
+                    .collect();
+                (key, sigs)
+            })
+        })
+        .collect();
+    let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    for (key, sigs) in results.iter() {
+        assert_eq!(key.aggregate_pubkey, results[0].0.aggregate_pubkey);
+        assert_eq!(key.participants, results[0].0.participants);
+        assert!(sigs.iter().all(|sig| sig.is_ok()));
+    }
+}
+
+#[test]
+fn twopc_key_share() {
+    use std::os::unix::net::UnixStream;
+    let (mut a, mut b) = UnixStream::pair().unwrap();
+    let m = scalars::Scalar::random();
+    let h = std::thread::spawn(move || {
+        let key = protocol::ecdsa::twopc::keygen(&mut a).unwrap();
+        let sig = protocol::ecdsa::twopc::sign(&key, super::util::background_inverse, &m, a);
+        (key, sig)
+    });
+    let key = protocol::ecdsa::twopc::keygen(&mut b).unwrap();
+    let sig = protocol::ecdsa::twopc::sign(&key, super::util::background_inverse, &m, b);
+    let (peer_key, peer_sig) = h.join().unwrap();
+    assert_eq!(key.aggregate_pubkey, peer_key.aggregate_pubkey);
+    assert!(sig.is_ok());
+    assert_eq!(sig.ok(), peer_sig.ok());
+}
+
+#[test]
+fn twopc_refuses_key_of_other_participants() {
+    use std::os::unix::net::UnixStream;
+    let ctx = &secp256k1::Secp256k1::new();
+    let secret = scalars::Scalar::random();
+    let point = secret.base_point(ctx).unwrap();
+    let other = scalars::Scalar::random().base_point(ctx).unwrap();
+    let m = scalars::Scalar::random();
+    for participants in [vec![point], vec![other, other], vec![other, point, other]] {
+        let key = protocol::ecdsa::keygen::KeyShare {
+            secret: secret.into(),
+            tweaked_secret: secret.into(),
+            aggregate_pubkey: point,
+            participants,
+        };
+        let (a, _b) = UnixStream::pair().unwrap();
+        let sig = protocol::ecdsa::twopc::sign(&key, super::util::background_inverse, &m, a);
+        assert!(sig.is_err());
+    }
+}
+
+#[test]
+fn twopc_async_with_blocking_peer() {
+    use futures::io::AllowStdIo;
+    use std::os::unix::net::UnixStream;
+    let (a, b) = UnixStream::pair().unwrap();
+    let m = scalars::Scalar::random();
+    let h = std::thread::spawn(move || {
+        futures::executor::block_on(protocol::ecdsa::twopc::run_async(&m, AllowStdIo::new(a)))
     });
+    let sig = protocol::ecdsa::twopc::run(super::util::background_inverse, &m, b);
+    assert!(sig.is_ok());
+    assert_eq!(sig.ok(), h.join().unwrap().ok());
 }
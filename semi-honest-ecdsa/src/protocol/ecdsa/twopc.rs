@@ -1,71 +1,143 @@
 pub use sha2::{Digest, Sha256};
-pub fn run<T: 'static, Inv>(
+use super::keygen::{KeyAggregation, KeyShare};
+use crate::error::{Error, Result};
+use crate::protocol::mult::Radix;
+use crate::scalars::Scalar;
+use crate::wire::{AsyncFramed, Channel, Framed, Kind};
+use futures::executor::block_on;
+use futures::io::{AsyncRead, AsyncWrite};
+use zeroize::Zeroizing;
+pub fn run<T, Inv>(
     get_inverse: Inv,
-    m: &[u64; 4],
+    m: &Scalar,
     mut peer: T,
-) -> Option<secp256k1::Signature>
+) -> Result<secp256k1::ecdsa::Signature>
 where
-    T: crate::util::ReadWrite + crate::util::HasTryClone,
+    T: crate::util::ReadWrite,
     Inv: FnOnce() -> super::util::Inverse,
 {
     // start computing nonce *now*, inverse is slow
     let inverse = get_inverse();
-    let key = crate::scalars::random_scalar();
+    let key = keygen(&mut peer)?;
+    sign(&key, move || inverse, m, peer)
+}
+
+/// run_async is run over an async stream, inverting the nonce on the
+/// caller's task when it is needed rather than on a background thread.
+pub async fn run_async<T>(m: &Scalar, mut peer: T) -> Result<secp256k1::ecdsa::Signature>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let key = keygen_async(&mut peer).await?;
+    sign_async(&key, m, peer).await
+}
 
-    let b32 = crate::scalars::bytes_from_scalar(&key);
+/// keygen generates a fresh MuSig key with the peer, ordering the two
+/// participants by public key.
+pub fn keygen<T>(peer: &mut T) -> Result<KeyShare>
+where
+    T: crate::util::ReadWrite,
+{
+    block_on(keygen_on(&mut Framed::new(peer, super::util::SETUP_SESSION)))
+}
+
+pub async fn keygen_async<T>(peer: &mut T) -> Result<KeyShare>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    keygen_on(&mut AsyncFramed::new(peer, super::util::SETUP_SESSION)).await
+}
+
+async fn keygen_on(peer: &mut dyn Channel) -> Result<KeyShare> {
+    let key = Zeroizing::new(Scalar::random());
 
     let ctx = &secp256k1::Secp256k1::new();
-    let my_pk = secp256k1::PublicKey::from_secret_key(
-        &ctx,
-        &secp256k1::SecretKey::from_slice(ctx, &b32).ok()?,
-    );
-    peer.write_all(&my_pk.serialize()[..]).ok()?;
-    peer.flush().ok()?;
+    let my_pk = key.base_point(ctx)?;
+    peer.send_frame(Kind::PublicKey, &my_pk.serialize()[..]).await?;
 
     let peer_pk = {
         let mut x = [0u8; 33];
-        peer.read_exact(&mut x[..]).ok()?;
-        secp256k1::PublicKey::from_slice(ctx, &x).ok()?
+        peer.recv_frame(Kind::PublicKey, &mut x[..]).await?;
+        secp256k1::PublicKey::from_slice(&x).map_err(|_| Error::InvalidPoint)?
     };
-    assert_ne!(peer_pk, my_pk);
-    let leader = my_pk > peer_pk;
-    let mut keys = if leader {
-        [peer_pk, my_pk]
+    let (keys, me) = if my_pk > peer_pk {
+        (vec![peer_pk, my_pk], 1)
     } else {
-        [my_pk, peer_pk]
+        (vec![my_pk, peer_pk], 0)
     };
+    super::keygen::aggregate(ctx, KeyAggregation::MuSig, keys, me, &key)
+}
 
-    let l = Sha256::new()
-        .chain(&keys[0].serialize()[..])
-        .chain(&keys[1].serialize()[..])
-        .result();
-    let lx = Sha256::new().chain(l.as_slice());
-
-    let my_tweaked_pk = {
-        let h = lx.clone().chain(&my_pk.serialize()[..]).result();
-        let mut z = [0u8; 32];
-        z.clone_from_slice(&h.as_slice());
-        let hs = crate::scalars::secp256k1_scalar_set_b32(&z);
-        crate::scalars::secp256k1_scalar_mul(&hs, &key)
-    };
-    let our_key = {
-        keys.iter_mut()
-            .filter(|k| **k != my_pk)
-            .try_fold(
-                secp256k1::PublicKey::from_secret_key(
-                    ctx,
-                    &secp256k1::SecretKey::from_slice(
-                        ctx,
-                        &crate::scalars::bytes_from_scalar(&my_tweaked_pk),
-                    ).ok()?,
-                ),
-                |acc, k| {
-                    let h = lx.clone().chain(&k.serialize()[..]).result();
-                    k.mul_assign(ctx, &secp256k1::SecretKey::from_slice(ctx, &h.as_slice())?)?;
-                    acc.combine(ctx, k)
-                },
-            ).ok()?
+/// session frames a signature under the id both parties derive from the
+/// key and message.
+fn session(key: &KeyShare, m: &Scalar) -> u32 {
+    crate::wire::session_id(&[
+        &key.aggregate_pubkey.serialize()[..],
+        &m.to_bytes()[..],
+    ])
+}
+
+/// sign signs m under a key from keygen, the participant with the greater
+/// public key leading.
+pub fn sign<T, Inv>(
+    key: &KeyShare,
+    get_inverse: Inv,
+    m: &Scalar,
+    peer: T,
+) -> Result<secp256k1::ecdsa::Signature>
+where
+    T: crate::util::ReadWrite,
+    Inv: FnOnce() -> super::util::Inverse,
+{
+    let (nonce, inverse) = get_inverse();
+    block_on(sign_on(
+        key,
+        nonce,
+        move || super::util::joined_inverse(inverse),
+        m,
+        &mut Framed::new(peer, session(key, m)),
+    ))
+}
+
+pub async fn sign_async<T>(
+    key: &KeyShare,
+    m: &Scalar,
+    peer: T,
+) -> Result<secp256k1::ecdsa::Signature>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let nonce = Zeroizing::new(Scalar::random());
+    let k = nonce.clone();
+    sign_on(
+        key,
+        nonce,
+        move || Ok(Zeroizing::new(k.inv())),
+        m,
+        &mut AsyncFramed::new(peer, session(key, m)),
+    )
+    .await
+}
+
+async fn sign_on<Inv>(
+    key: &KeyShare,
+    nonce: Zeroizing<Scalar>,
+    get_inverse: Inv,
+    m: &Scalar,
+    peer: &mut dyn Channel,
+) -> Result<secp256k1::ecdsa::Signature>
+where
+    Inv: FnOnce() -> Result<Zeroizing<Scalar>>,
+{
+    let ctx = &secp256k1::Secp256k1::new();
+    let my_pk = key.secret.base_point(ctx)?;
+    // the participant with the greater public key comes second and leads
+    let leader = match key.participants.iter().position(|k| *k == my_pk) {
+        Some(me) if key.participants.len() == 2 => me == 1,
+        _ => return Err(Error::ProtocolAbort("key is not shared by two participants")),
     };
+    let my_tweaked_pk = &key.tweaked_secret;
+    let our_key = key.aggregate_pubkey;
 
     // We have
     // q2q1( M + r (k1 + k2))
@@ -80,156 +152,146 @@ where
     //  (s_0 + s_1 )
 
     let (r, s) = if leader {
-        run_leader(ctx, m, inverse, &my_tweaked_pk, peer)?
+        run_leader(ctx, m, nonce, get_inverse, my_tweaked_pk, peer).await?
     } else {
-        run_follower(ctx, inverse, &my_tweaked_pk, peer)?
+        run_follower(ctx, nonce, get_inverse, my_tweaked_pk, peer).await?
     };
     let mut x = [0; 64];
-    x[0..=31].clone_from_slice(&crate::scalars::bytes_from_scalar(&r)[..]);
-    x[32..].clone_from_slice(&crate::scalars::bytes_from_scalar(&s)[..]);
+    x[0..=31].clone_from_slice(&r.to_bytes()[..]);
+    x[32..].clone_from_slice(&s.to_bytes()[..]);
 
-    let mut sig = secp256k1::Signature::from_compact(ctx, &x[..]).ok()?;
-    sig.normalize_s(ctx);
+    let mut sig = secp256k1::ecdsa::Signature::from_compact(&x[..])?;
+    sig.normalize_s();
 
-    let msg = secp256k1::Message::from_slice(&crate::scalars::bytes_from_scalar(&m)[..]).ok()?;
-    ctx.verify(&msg, &sig, &our_key).ok()?;
-    Some(sig)
+    let msg = secp256k1::Message::from_digest_slice(&m.to_bytes()[..])?;
+    ctx.verify_ecdsa(&msg, &sig, &our_key)
+        .map_err(|_| Error::VerificationFailed)?;
+    Ok(sig)
 }
 
-fn run_leader<T: 'static, C>(
+async fn run_leader<C, Inv>(
     ctx: &secp256k1::Secp256k1<C>,
-    m: &[u64; 4],
-    nonce_pair: super::util::Inverse,
-    my_tweaked_pk: &[u64; 4],
-    mut peer: T,
-) -> Option<(crate::scalars::scalar, crate::scalars::scalar)>
+    m: &Scalar,
+    nonce: Zeroizing<Scalar>,
+    get_inverse: Inv,
+    my_tweaked_pk: &Scalar,
+    peer: &mut dyn Channel,
+) -> Result<(Scalar, Scalar)>
 where
-    T: crate::util::ReadWrite + crate::util::HasTryClone,
     C: secp256k1::Signing + secp256k1::Verification,
+    Inv: FnOnce() -> Result<Zeroizing<Scalar>>,
 {
-    let nonce = nonce_pair.0;
     let r = {
-        let b32_nonce =
-            secp256k1::SecretKey::from_slice(ctx, &crate::scalars::bytes_from_scalar(&nonce)[..])
-                .ok()?;
-        let k_g = secp256k1::PublicKey::from_secret_key(ctx, &b32_nonce);
-        peer.write_all(&k_g.serialize()[..]).ok()?;
-        peer.flush().ok()?;
+        let k_g = nonce.base_point(ctx)?;
+        peer.send_frame(Kind::NoncePoint, &k_g.serialize()[..]).await?;
         let mut xb = [0; 32];
-        peer.read_exact(&mut xb[..]).ok()?;
-        crate::scalars::secp256k1_scalar_set_b32(&xb)
+        peer.recv_frame(Kind::NonceX, &mut xb[..]).await?;
+        Scalar::from_bytes(&xb)
     };
     let s = {
-        let mut kx_m = crate::scalars::secp256k1_scalar_mul(my_tweaked_pk, &r);
-        crate::scalars::secp256k1_scalar_add_assign(&mut kx_m, &m);
+        let kx_m = *my_tweaked_pk * r + *m;
         // kx_m = M + r k
 
         // kx_m_in = g_0
-        let i_nonce = nonce_pair.1.join().ok()?;
-        let kx_m_in = crate::scalars::secp256k1_scalar_mul(&i_nonce, &kx_m);
+        let i_nonce = get_inverse()?;
+        let kx_m_in = *i_nonce * kx_m;
 
         // They Will Request
         // gamma1 = g_1
         let gamma1 = {
-            let mut gamma1 =
-                crate::protocol::mult::receiver::run_scale_free(&i_nonce, peer.try_clone())
-                    .join()
-                    .ok()?;
-            crate::scalars::secp256k1_scalar_add_assign(&mut gamma1, &kx_m_in);
-            gamma1
+            let gamma1 = crate::protocol::mult::receiver::run_scale_free_async(
+                &i_nonce,
+                Radix::default(),
+                &mut *peer.sub_channel(),
+            )
+            .await?;
+            gamma1 + kx_m_in
         };
         // gamma1 = d_1
 
         // We will request
         // gamma2 = t_0 = s_0
-        let (send_gamma1, gamma2, th) = crate::protocol::mult::sender::run_scale_free_stupid_parallel(peer.try_clone());
-        send_gamma1.send(gamma1).ok()?;
-        th.join().ok()?;
+        let gamma2 = crate::protocol::mult::sender::run_scale_free_async(
+            &gamma1,
+            Radix::default(),
+            &mut *peer.sub_channel(),
+        )
+        .await?;
         // Share it gamma2 to construct fina sig..
-        peer.write_all(&crate::scalars::bytes_from_scalar(&gamma2)[..])
-            .ok()?;
-        peer.flush().ok()?;
+        peer.send_frame(Kind::Share, &gamma2.to_bytes()[..]).await?;
         let gamma3 = {
             let mut buf = [0; 32];
-            peer.read_exact(&mut buf[..]).ok()?;
+            peer.recv_frame(Kind::Share, &mut buf[..]).await?;
             // gamma3 = s_1
-            let mut gamma3 = crate::scalars::secp256k1_scalar_set_b32(&buf);
-            crate::scalars::secp256k1_scalar_add_assign(&mut gamma3, &gamma2);
-            gamma3
+            Scalar::from_bytes(&buf) + gamma2
         };
         gamma3
     };
-    Some((r, s))
+    Ok((r, s))
 }
 
-fn run_follower<T: 'static, C>(
+async fn run_follower<C, Inv>(
     ctx: &secp256k1::Secp256k1<C>,
-    nonce_pair: super::util::Inverse,
-    my_tweaked_pk: &[u64; 4],
-    mut peer: T,
-) -> Option<(crate::scalars::scalar, crate::scalars::scalar)>
+    nonce: Zeroizing<Scalar>,
+    get_inverse: Inv,
+    my_tweaked_pk: &Scalar,
+    peer: &mut dyn Channel,
+) -> Result<(Scalar, Scalar)>
 where
-    T: crate::util::ReadWrite + crate::util::HasTryClone,
     C: secp256k1::Signing + secp256k1::Verification,
+    Inv: FnOnce() -> Result<Zeroizing<Scalar>>,
 {
-    let nonce = nonce_pair.0;
     let r = {
-        let b32_nonce =
-            secp256k1::SecretKey::from_slice(ctx, &crate::scalars::bytes_from_scalar(&nonce)[..])
-                .ok()?;
         let kk_g = {
             let mut b33 = [0u8; 33];
-            peer.read_exact(&mut b33[..]).ok()?;
-            let mut k_g = secp256k1::PublicKey::from_slice(ctx, &b33).ok()?;
-            k_g.mul_assign(ctx, &b32_nonce).ok()?;
-            k_g
+            peer.recv_frame(Kind::NoncePoint, &mut b33[..]).await?;
+            let k_g = secp256k1::PublicKey::from_slice(&b33).map_err(|_| Error::InvalidPoint)?;
+            nonce.mul_point(ctx, &k_g)?
         };
-        peer.write_all(&kk_g.serialize()[1..]).ok()?;
-        peer.flush().ok()?;
+        peer.send_frame(Kind::NonceX, &kk_g.serialize()[1..]).await?;
 
         let mut xb = [0; 32];
         xb[..].clone_from_slice(&kk_g.serialize()[1..]);
-        crate::scalars::secp256k1_scalar_set_b32(&xb)
+        Scalar::from_bytes(&xb)
     };
     let s = {
+        // kx = rk2
+        let kx = *my_tweaked_pk * r;
         // We Will Request
         // gamma1 = g_2 = d_2
-        let (send_kx, gamma1, wait_before_send) =
-            crate::protocol::mult::sender::run_scale_free_stupid_parallel(peer.try_clone());
+        let gamma1 = crate::protocol::mult::sender::run_scale_free_async(
+            &kx,
+            Radix::default(),
+            &mut *peer.sub_channel(),
+        )
+        .await?;
         // They will request
         // gamma1_in = d_2 * q2 = t_2
-        let i_nonce = nonce_pair.1.join().ok()?;
-        let gamma1_in = crate::scalars::secp256k1_scalar_mul(&i_nonce, &gamma1);
+        let i_nonce = get_inverse()?;
+        let gamma1_in = *i_nonce * gamma1;
 
-        // kx = rk2
-        let kx = crate::scalars::secp256k1_scalar_mul(my_tweaked_pk, &r);
-        send_kx.send(kx).ok()?;
-        wait_before_send.join().ok()?;
         // gamma2 = t_1
         let gamma2 = {
-            let mut gamma2 =
-                crate::protocol::mult::receiver::run_scale_free(&i_nonce, peer.try_clone())
-                    .join()
-                    .ok()?;
+            let gamma2 = crate::protocol::mult::receiver::run_scale_free_async(
+                &i_nonce,
+                Radix::default(),
+                &mut *peer.sub_channel(),
+            )
+            .await?;
             // t1+t2 = s_1
-            crate::scalars::secp256k1_scalar_add_assign(&mut gamma2, &gamma1_in);
-            gamma2
+            gamma2 + gamma1_in
         };
 
         // Share s_1
-        peer.write_all(&crate::scalars::bytes_from_scalar(&gamma2)[..])
-            .ok()?;
-        peer.flush().ok()?;
+        peer.send_frame(Kind::Share, &gamma2.to_bytes()[..]).await?;
         // Read s_0 into xb
         let gamma3 = {
             let mut xb = [0; 32];
-            peer.read_exact(&mut xb[..]).ok()?;
-            let mut gamma3 = crate::scalars::secp256k1_scalar_set_b32(&xb);
+            peer.recv_frame(Kind::Share, &mut xb[..]).await?;
             // s_0+s_1
-            crate::scalars::secp256k1_scalar_add_assign(&mut gamma3, &gamma2);
-            gamma3
+            Scalar::from_bytes(&xb) + gamma2
         };
         gamma3
     };
-    Some((r, s))
+    Ok((r, s))
 }
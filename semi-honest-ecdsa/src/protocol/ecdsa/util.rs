@@ -1,14 +1,15 @@
 use std::thread::{spawn, JoinHandle};
-pub type Inverse = (crate::scalars::scalar, JoinHandle<crate::scalars::scalar>);
+use zeroize::Zeroizing;
+/// Inverse is a nonce share and its inverse, being computed in the
+/// background. Both are wiped when dropped.
+pub type Inverse = (
+    Zeroizing<crate::scalars::Scalar>,
+    JoinHandle<Zeroizing<crate::scalars::Scalar>>,
+);
 pub fn background_inverse() -> Inverse {
-    let nonce = crate::scalars::random_scalar();
-    {
-        let nonce_c = nonce.clone();
-        (
-            nonce,
-            spawn(move || crate::scalars::secp256k1_scalar_inverse(&nonce_c)),
-        )
-    }
+    let nonce = Zeroizing::new(crate::scalars::Scalar::random());
+    let k = nonce.clone();
+    (nonce, spawn(move || Zeroizing::new(k.inv())))
 }
 
 use std::sync::mpsc::{sync_channel, Receiver};
@@ -23,3 +24,62 @@ pub fn background_inverse_service(n: usize) -> Receiver<Inverse> {
     });
     receiver
 }
+
+/// peer_slot maps participant j to its connection in a peer list stored in
+/// participant order with our own index `me` left out.
+pub fn peer_slot(me: usize, j: usize) -> usize {
+    if j < me {
+        j
+    } else {
+        j - 1
+    }
+}
+
+/// joined_inverse waits on the background inversion of a nonce.
+pub fn joined_inverse(
+    h: JoinHandle<Zeroizing<crate::scalars::Scalar>>,
+) -> Result<Zeroizing<crate::scalars::Scalar>> {
+    h.join()
+        .map_err(|_| Error::ProtocolAbort("nonce inversion panicked"))
+}
+
+use crate::error::{Error, Result};
+use crate::util::{HasTryClone, ReadWrite};
+use crate::wire::{Framed, Kind, MAX_PAYLOAD};
+
+/// SETUP_SESSION frames the steps which run before there is anything shared
+/// to derive a session id from, i.e. key and nonce generation.
+pub const SETUP_SESSION: u32 = 0;
+
+/// exchange sends msg to every peer as `kind` frames and reads back a
+/// message of the same length from each, writing from a background thread
+/// per peer so large batches cannot deadlock on full socket buffers.
+pub fn exchange<T>(peers: &mut [T], kind: Kind, session: u32, msg: &[u8]) -> Result<Vec<Vec<u8>>>
+where
+    T: 'static + ReadWrite + HasTryClone,
+{
+    let mut writers = Vec::with_capacity(peers.len());
+    for peer in peers.iter() {
+        let mut peer = Framed::new(peer.try_clone()?, session);
+        let msg = msg.to_vec();
+        writers.push(spawn(move || -> Result<()> {
+            for chunk in msg.chunks(MAX_PAYLOAD) {
+                peer.send(kind, chunk)?;
+            }
+            Ok(())
+        }));
+    }
+    let mut got = Vec::with_capacity(peers.len());
+    for peer in peers.iter_mut() {
+        let mut peer = Framed::new(peer, session);
+        let mut buf = vec![0u8; msg.len()];
+        for chunk in buf.chunks_mut(MAX_PAYLOAD) {
+            peer.recv(kind, chunk)?;
+        }
+        got.push(buf);
+    }
+    for w in writers {
+        crate::error::joined(w)?;
+    }
+    Ok(got)
+}
@@ -2,3 +2,71 @@ pub mod receiver;
 pub mod sender;
 #[cfg(test)]
 mod tests;
+use crate::error::{Error, Result};
+use crate::scalars::Scalar;
+use zeroize::Zeroizing;
+
+/// Step is what a multiplication state machine turns into after a message:
+/// either the next state, or its result once the last OT is done.
+pub enum Step<S, T> {
+    Next(S),
+    Done(T),
+}
+
+/// Stepped is a Step and the OT messages it sends.
+pub type Stepped<S, T> = (Step<S, T>, Vec<crate::protocol::ot::Msg>);
+
+/// Radix is how many bits of the receiver's scalar each OT of a scale free
+/// multiplication carries, from 1 to 8.
+///
+/// Each OT offers 2^bits entries, so 8 bits is 32 OTs of 256 entries (about
+/// 256 KiB) and 1 bit is 256 OTs of 2 entries (about 16 KiB), trading OTs
+/// for bandwidth. Both parties must use the same radix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Radix(u8);
+
+impl Radix {
+    pub fn new(bits: u8) -> Result<Radix> {
+        if !(1..=8).contains(&bits) {
+            return Err(Error::ProtocolAbort("radix must be 1 to 8 bits"));
+        }
+        Ok(Radix(bits))
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// entries is the number of entries each OT offers.
+    pub fn entries(self) -> usize {
+        1 << self.0
+    }
+
+    /// digits is the number of OTs, one per digit of a 256 bit scalar.
+    pub fn digits(self) -> usize {
+        256_usize.div_ceil(self.0 as usize)
+    }
+
+    /// digits_of splits s into its digits, least significant first.
+    pub fn digits_of(self, s: &Scalar) -> Zeroizing<Vec<u8>> {
+        let b = Zeroizing::new(s.to_bytes());
+        let bit = |k: usize| (b[31 - k / 8] >> (k % 8)) & 1;
+        let bits = self.0 as usize;
+        Zeroizing::new(
+            (0..self.digits())
+                .map(|i| {
+                    (0..bits)
+                        .filter(|t| i * bits + t < 256)
+                        .fold(0, |d, t| d | bit(i * bits + t) << t)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Default for Radix {
+    /// The default is 8 bits, one OT per byte.
+    fn default() -> Radix {
+        Radix(8)
+    }
+}
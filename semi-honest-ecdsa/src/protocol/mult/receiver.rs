@@ -1,68 +1,206 @@
+use super::{Radix, Step, Stepped};
+use crate::error::{Error, Result};
+use crate::protocol::ot::extension::ReceiverPool;
+use crate::protocol::ot::{one_of_n, Msg};
+use crate::wire::{Channel, Framed, Kind};
 use crate::*;
-use std::sync::mpsc::*;
+use std::collections::VecDeque;
 use std::thread;
-pub fn run<T: 'static>(beta: &scalars::scalar, peer: T) -> thread::JoinHandle<scalars::scalar>
-where
-    T: HasTryClone + ReadWrite + Send,
-{
-    let (tx, rx) = channel();
-    let r = thread::spawn(move || {
-        let mut sigma_beta = [0u64; 4];
-        for (mut v, shift) in rx.iter().take(32).zip((0u8..32u8).rev()) {
-            scalars::non_constant_time_shift(&mut v, shift);
-            scalars::secp256k1_scalar_add_assign(&mut sigma_beta, &v);
+
+/// Choose runs one OT of radix.entries() per choice, as a state machine
+/// doing no I/O of its own. The OTs share the sender's S, so every R goes
+/// out at once in answer to it.
+pub struct Choose {
+    radix: Radix,
+    choices: Zeroizing<Vec<u8>>,
+    vs: Zeroizing<Vec<scalars::Scalar>>,
+    ots: Option<VecDeque<protocol::ot::receiver::protocol::sent_r>>,
+}
+
+impl Choose {
+    pub fn new(choices: &[u8], radix: Radix) -> Result<Choose> {
+        if choices.is_empty() {
+            return Err(Error::ProtocolAbort("nothing to choose"));
+        }
+        if choices.iter().fold(0, |a, c| a | c) as usize >= radix.entries() {
+            return Err(Error::ProtocolAbort("choice out of radix"));
+        }
+        Ok(Choose {
+            radix,
+            choices: Zeroizing::new(choices.to_vec()),
+            vs: Zeroizing::new(Vec::with_capacity(choices.len())),
+            ots: None,
+        })
+    }
+
+    /// expects is the kind of message the next step takes.
+    pub fn expects(&self) -> Kind {
+        match self.ots {
+            Some(_) => Kind::OtCiphertexts,
+            None => Kind::OtS,
         }
-        sigma_beta
-    });
+    }
+
+    /// step takes the sender's S, answering with an R for every choice, or
+    /// the ciphertexts of the next OT, keeping the one we chose. It is done
+    /// after the last choice.
+    pub fn step<C>(
+        mut self,
+        ctx: &secp256k1::Secp256k1<C>,
+        incoming: Msg,
+    ) -> Result<Stepped<Choose, Zeroizing<Vec<scalars::Scalar>>>>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
     {
-        let beta = beta.clone();
-        let mut peer_clone = peer.try_clone();
-        thread::spawn(move || {
-            // MSB to LSB
-            let ctx = &secp256k1::Secp256k1::new();
-            for choice in scalars::bytes_from_scalar(&beta).iter() {
-                tx.send(protocol::ot::receiver::run(
-                    ctx,
-                    *choice,
-                    xor_decipher_scalar,
-                    &mut peer_clone,
-                ));
+        let ots = match self.ots.as_mut() {
+            Some(ots) => ots,
+            None => {
+                let got_s = protocol::ot::receiver::protocol::got_s::new(ctx, incoming)?;
+                let mut ots = VecDeque::with_capacity(self.choices.len());
+                let mut out = Vec::with_capacity(self.choices.len());
+                for (index, choice) in self.choices.iter().enumerate() {
+                    let (ot, r) = got_s.choose(ctx, index as u32, *choice)?;
+                    ots.push_back(ot);
+                    out.extend(r);
+                }
+                self.ots = Some(ots);
+                return Ok((Step::Next(self), out));
             }
-        });
+        };
+        let ot = ots
+            .pop_front()
+            .ok_or(Error::ProtocolAbort("multiplication ended early"))?;
+        self.vs.push(ot.step(incoming, xor_decipher_scalar)?);
+        if self.vs.len() == self.choices.len() {
+            Ok((Step::Done(std::mem::take(&mut self.vs)), vec![]))
+        } else {
+            Ok((Step::Next(self), vec![]))
+        }
+    }
+}
+
+/// choose drives a Choose over peer.
+async fn choose(
+    choices: &[u8],
+    radix: Radix,
+    peer: &mut dyn Channel,
+) -> Result<Zeroizing<Vec<scalars::Scalar>>> {
+    let ctx = &secp256k1::Secp256k1::new();
+    let mut chooser = Choose::new(choices, radix)?;
+    loop {
+        let n = chooser.radix.entries();
+        let incoming = protocol::ot::recv_msg(peer, chooser.expects(), n).await?;
+        let (next, out) = chooser.step(ctx, incoming)?;
+        protocol::ot::send_msgs(peer, out).await?;
+        match next {
+            Step::Next(c) => chooser = c,
+            Step::Done(vs) => return Ok(vs),
+        }
     }
-    r
 }
 
-pub fn run_scale_free<T: 'static>(
-    beta: &scalars::scalar,
-    peer: T,
-) -> thread::JoinHandle<scalars::scalar>
+pub fn run<T>(
+    beta: &scalars::Scalar,
+    mut peer: Framed<T>,
+) -> thread::JoinHandle<Result<scalars::Scalar>>
 where
-    T: ReadWrite + HasTryClone + Send,
+    T: 'static + HasTryClone + ReadWrite + Send,
 {
-    let (tx, rx) = channel();
-    let r = thread::spawn(move || {
-        let mut sigma_beta = [0u64; 4];
-        for mut v in rx.iter().take(32) {
-            scalars::secp256k1_scalar_add_assign(&mut sigma_beta, &v);
+    // MSB to LSB
+    let choices = Zeroizing::new(beta.to_bytes());
+    thread::spawn(move || {
+        let vs = futures::executor::block_on(choose(&choices[..], Radix::default(), &mut peer))?;
+        let mut sigma_beta = scalars::Scalar::ZERO;
+        for (v, shift) in vs.iter().zip((0u8..32u8).rev()) {
+            let mut v = Zeroizing::new(*v);
+            scalars::shift(&mut v, shift);
+            sigma_beta += *v;
         }
-        sigma_beta
-    });
+        Ok(sigma_beta)
+    })
+}
+
+pub fn run_scale_free<T>(
+    beta: &scalars::Scalar,
+    radix: Radix,
+    mut peer: Framed<T>,
+) -> thread::JoinHandle<Result<scalars::Scalar>>
+where
+    T: 'static + ReadWrite + HasTryClone + Send,
+{
+    let beta = Zeroizing::new(*beta);
+    thread::spawn(move || {
+        futures::executor::block_on(run_scale_free_async(&beta, radix, &mut peer))
+    })
+}
+
+/// run_scale_free_async is run_scale_free on the caller's task.
+pub async fn run_scale_free_async(
+    beta: &scalars::Scalar,
+    radix: Radix,
+    peer: &mut dyn Channel,
+) -> Result<scalars::Scalar> {
+    Ok(run_scale_free_batch_async(beta, 1, radix, peer).await?[0])
+}
+
+/// run_scale_free_batch is run_scale_free_batch_async on the calling
+/// thread, returning our share of each product.
+pub fn run_scale_free_batch<T>(
+    beta: &scalars::Scalar,
+    count: usize,
+    radix: Radix,
+    mut peer: Framed<T>,
+) -> Result<Vec<scalars::Scalar>>
+where
+    T: ReadWrite,
+{
+    futures::executor::block_on(run_scale_free_batch_async(beta, count, radix, &mut peer))
+}
+
+/// run_scale_free_batch_async multiplies beta by each of count alphas of
+/// the peer's, running sender::run_scale_free_batch_async, in a single
+/// batch of OTs.
+pub async fn run_scale_free_batch_async(
+    beta: &scalars::Scalar,
+    count: usize,
+    radix: Radix,
+    peer: &mut dyn Channel,
+) -> Result<Vec<scalars::Scalar>> {
+    // LSB to MSB, once per product
+    let digits = radix.digits_of(beta);
+    let choices: Zeroizing<Vec<u8>> = Zeroizing::new(digits.repeat(count));
+    let vs = choose(&choices[..], radix, peer).await?;
+    Ok(vs.chunks_exact(radix.digits()).map(|v| v.iter().sum()).collect())
+}
+
+/// run_scale_free_extended_async is run_scale_free_async with each
+/// 1-of-radix.entries() OT built from radix.bits() OTs drawn from pool, as in
+/// ot::one_of_n. The peer must be running sender::run_scale_free_extended_async
+/// with the SenderPool pool was set up against.
+pub async fn run_scale_free_extended_async(
+    beta: &scalars::Scalar,
+    radix: Radix,
+    pool: &mut ReceiverPool,
+    peer: &mut dyn Channel,
+) -> Result<scalars::Scalar> {
     // LSB to MSB
-    {
-        let beta = beta.clone();
-        let mut peer_clone = peer.try_clone();
-        thread::spawn(move || {
-            let ctx = &secp256k1::Secp256k1::new();
-            for choice in scalars::bytes_from_scalar(&beta).iter().rev() {
-                tx.send(protocol::ot::receiver::run(
-                    ctx,
-                    *choice,
-                    xor_decipher_scalar,
-                    &mut peer_clone,
-                ));
-            }
-        });
+    let digits = radix.digits_of(beta);
+    let bits = radix.bits() as usize;
+    let choices: Zeroizing<Vec<u8>> = Zeroizing::new(
+        digits
+            .iter()
+            .flat_map(|d| (0..bits).map(move |b| (d >> b) & 1))
+            .collect(),
+    );
+    let keys = pool.extend_async(&choices[..], peer).await?;
+    let mut sigma_beta = scalars::Scalar::ZERO;
+    for (d, keys) in digits.iter().zip(keys.chunks_exact(bits)) {
+        let es = match protocol::ot::recv_msg(peer, Kind::OtCiphertexts, radix.entries()).await? {
+            Msg::Ciphertexts(es) => es,
+            _ => return Err(Error::ProtocolAbort("OT receiver expected ciphertexts")),
+        };
+        let v = one_of_n::open(keys, *d as usize, &es[..], xor_decipher_scalar);
+        sigma_beta += v;
     }
-    r
+    Ok(sigma_beta)
 }
@@ -1,123 +1,322 @@
+use super::{Radix, Step, Stepped};
+use crate::error::{Error, Result};
+use crate::protocol::ot::extension::SenderPool;
+use crate::protocol::ot::{one_of_n, Msg};
+use crate::wire::{Channel, Framed, Kind};
 use crate::*;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
-pub fn run<T: 'static>(
-    alpha: &scalars::scalar,
-    peer: T,
-) -> (scalars::scalar, thread::JoinHandle<()>)
+
+/// Transfer offers each row of a multiplication table in its own OT, as a
+/// state machine doing no I/O of its own. There is a row per digit of the
+/// radix, each offering its first radix.entries(), for each multiplication
+/// of the batch. All the OTs share one S, so the receiver can send every R
+/// at once.
+pub struct Transfer<I> {
+    rows: I,
+    radix: Radix,
+    ot: protocol::ot::sender::protocol::sent_s,
+    sent: usize,
+    total: usize,
+}
+
+impl<I> Transfer<I>
+where
+    I: Iterator<Item = scalars::Table>,
+{
+    /// start sends S for a single multiplication.
+    pub fn start<C>(
+        ctx: &secp256k1::Secp256k1<C>,
+        radix: Radix,
+        rows: I,
+    ) -> Result<(Transfer<I>, Vec<Msg>)>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        Transfer::start_batch(ctx, radix, 1, rows)
+    }
+
+    /// start_batch sends S for a batch of count multiplications, whose rows
+    /// follow one another.
+    pub fn start_batch<C>(
+        ctx: &secp256k1::Secp256k1<C>,
+        radix: Radix,
+        count: usize,
+        rows: I,
+    ) -> Result<(Transfer<I>, Vec<Msg>)>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        let total = count * radix.digits();
+        if total == 0 || total > u32::MAX as usize {
+            return Err(Error::ProtocolAbort("bad multiplication batch size"));
+        }
+        let (ot, out) = protocol::ot::sender::protocol::start::new().step(ctx)?;
+        let transfer = Transfer {
+            rows,
+            radix,
+            ot,
+            sent: 0,
+            total,
+        };
+        Ok((transfer, out))
+    }
+
+    /// step takes the receiver's R for the next row and sends it the row,
+    /// failing if the rows stop coming early.
+    pub fn step<C>(
+        mut self,
+        ctx: &secp256k1::Secp256k1<C>,
+        incoming: Msg,
+    ) -> Result<Stepped<Transfer<I>, ()>>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        let row = self
+            .rows
+            .next()
+            .ok_or(Error::ProtocolAbort("multiplication ended early"))?;
+        let entries = &row[..self.radix.entries()];
+        let out = self
+            .ot
+            .step_indexed(ctx, self.sent as u32, incoming, entries, xor_cipher)?;
+        self.sent += 1;
+        if self.sent == self.total {
+            return Ok((Step::Done(()), out));
+        }
+        Ok((Step::Next(self), out))
+    }
+}
+
+/// transfer_async drives a Transfer of count multiplications over peer.
+///
+/// Every R is read before any row goes out, so neither side blocks writing
+/// while the other does too, however large the batch.
+async fn transfer_async<I>(
+    rows: I,
+    radix: Radix,
+    count: usize,
+    peer: &mut dyn Channel,
+) -> Result<()>
 where
-    T: ReadWrite + HasTryClone + Send,
+    I: Iterator<Item = scalars::Table>,
 {
-    let mut neg_sigma_alpha = [0u64; 4];
+    let ctx = &secp256k1::Secp256k1::new();
+    let (mut transfer, out) = Transfer::start_batch(ctx, radix, count, rows)?;
+    protocol::ot::send_msgs(peer, out).await?;
+    let mut rs = Vec::with_capacity(transfer.total);
+    for _ in 0..transfer.total {
+        rs.push(protocol::ot::recv_msg(peer, Kind::OtR, 256).await?);
+    }
+    for r in rs {
+        let (next, out) = transfer.step(ctx, r)?;
+        protocol::ot::send_msgs(peer, out).await?;
+        match next {
+            Step::Next(t) => transfer = t,
+            Step::Done(()) => return Ok(()),
+        }
+    }
+    Err(Error::ProtocolAbort("multiplication ended early"))
+}
+
+/// transfer runs transfer_async on its own thread, taking rows as they are
+/// computed.
+fn transfer<T>(
+    rx: Receiver<scalars::Table>,
+    radix: Radix,
+    mut peer: Framed<T>,
+) -> thread::JoinHandle<Result<()>>
+where
+    T: 'static + ReadWrite,
+{
+    thread::spawn(move || {
+        futures::executor::block_on(transfer_async(rx.iter(), radix, 1, &mut peer))
+    })
+}
+
+/// negated_sum is the sender's share -(neg_phi_0 + ... + neg_phi_31).
+fn negated_sum(neg_phis: &[scalars::Scalar]) -> scalars::Scalar {
+    -neg_phis.iter().sum::<scalars::Scalar>()
+}
+
+pub fn run<T>(
+    alpha: &scalars::Scalar,
+    peer: Framed<T>,
+) -> (scalars::Scalar, thread::JoinHandle<Result<()>>)
+where
+    T: 'static + ReadWrite + HasTryClone + Send,
+{
+    let mut neg_sigma_alpha = scalars::Scalar::ZERO;
     let t = {
         // MSB to LSB
-        let (tx, rx) = channel::<[[u64; 4]; 256]>();
-        let t = {
-            let mut peer_clone = peer.try_clone();
-            thread::spawn(move || {
-                let ctx = &secp256k1::Secp256k1::new();
-                for mut row in rx.iter().take(32) {
-                    protocol::ot::sender::run(&ctx, &mut row[..], xor_cipher, &mut peer_clone);
-                }
-            })
-        };
-        let mut alphas: [[u64; 4]; 256] = scalars::scalar_mul_by_256(&alpha);
+        let (tx, rx) = channel::<scalars::Table>();
+        let t = transfer(rx, Radix::default(), peer);
+        let alphas = scalars::scalar_mul_by_256(alpha);
         for count in (0..32).rev() {
-            let mut neg_phi = scalars::random_scalar();
-            let mut row = alphas.clone();
-            scalars::assign_add(&mut row, &neg_phi);
-            tx.send(row);
-            scalars::non_constant_time_shift(&mut neg_phi, count as u8);
-            scalars::secp256k1_scalar_add_assign(&mut neg_sigma_alpha, &neg_phi);
+            let mut neg_phi = Zeroizing::new(scalars::Scalar::random());
+            let mut row = scalars::Table::default();
+            row.copy_from_slice(&alphas[..]);
+            scalars::assign_add(&mut row[..], &neg_phi);
+            if tx.send(row).is_err() {
+                break;
+            }
+            scalars::shift(&mut neg_phi, count as u8);
+            neg_sigma_alpha += *neg_phi;
         }
         t
     };
 
-    scalars::secp256k1_scalar_negate(&mut neg_sigma_alpha);
-    (neg_sigma_alpha, t)
+    (-neg_sigma_alpha, t)
+}
+
+/// scale_free_row is row `count` of a scale free multiplication, moving
+/// alpha on from the previous row's digit to this one's.
+fn scale_free_row(
+    alpha: &mut scalars::Scalar,
+    count: usize,
+    neg_phi: &scalars::Scalar,
+    radix: Radix,
+) -> scalars::Table {
+    if count > 0 {
+        *alpha = alpha.mul_pow2(radix.bits() as u32);
+    }
+    let mut row = scalars::multiples(alpha, radix.entries());
+    scalars::assign_add(&mut row[..radix.entries()], neg_phi);
+    row
 }
 
-pub fn run_scale_free<T: 'static>(
-    alpha: &scalars::scalar,
-    peer: T,
-) -> (scalars::scalar, thread::JoinHandle<()>)
+pub fn run_scale_free<T>(
+    alpha: &scalars::Scalar,
+    radix: Radix,
+    peer: Framed<T>,
+) -> (scalars::Scalar, thread::JoinHandle<Result<()>>)
 where
-    T: ReadWrite + HasTryClone + Send,
+    T: 'static + ReadWrite + HasTryClone + Send,
 {
-    let mut neg_sigma_alpha = [0u64; 4];
+    let mut neg_sigma_alpha = scalars::Scalar::ZERO;
     let t = {
-        // MSB to LSB
-        let (tx, rx) = channel::<[[u64; 4]; 256]>();
-        let t = {
-            let mut peer_clone = peer.try_clone();
-            thread::spawn(move || {
-                let ctx = &secp256k1::Secp256k1::new();
-                for mut row in rx.iter().take(32) {
-                    protocol::ot::sender::run(&ctx, &mut row[..], xor_cipher, &mut peer_clone);
-                }
-            })
-        };
-        let mut alpha_doubles = alpha.clone();
-        for count in (0..32) {
-            scalars::non_constant_time_shift(&mut alpha_doubles, (count > 0) as u8);
-            let mut row: [[u64; 4]; 256] = scalars::scalar_mul_by_256(&alpha_doubles);
-            let mut neg_phi = scalars::random_scalar();
-            scalars::assign_add(&mut row, &neg_phi);
-            tx.send(row);
-            scalars::secp256k1_scalar_add_assign(&mut neg_sigma_alpha, &neg_phi);
+        // LSB to MSB
+        let (tx, rx) = channel::<scalars::Table>();
+        let t = transfer(rx, radix, peer);
+        let mut alpha_doubles = Zeroizing::new(*alpha);
+        for count in 0..radix.digits() {
+            let neg_phi = Zeroizing::new(scalars::Scalar::random());
+            let row = scale_free_row(&mut alpha_doubles, count, &neg_phi, radix);
+            if tx.send(row).is_err() {
+                break;
+            }
+            neg_sigma_alpha += *neg_phi;
         }
         t
     };
 
-    scalars::secp256k1_scalar_negate(&mut neg_sigma_alpha);
-    (neg_sigma_alpha, t)
+    (-neg_sigma_alpha, t)
 }
 
-pub fn run_scale_free_stupid_parallel<T: 'static>(
-    peer: T,
-) -> (Sender<scalars::scalar>, scalars::scalar, thread::JoinHandle<()>)
+/// run_scale_free_async is run_scale_free on the caller's task, returning
+/// our share once the peer has every row.
+pub async fn run_scale_free_async(
+    alpha: &scalars::Scalar,
+    radix: Radix,
+    peer: &mut dyn Channel,
+) -> Result<scalars::Scalar> {
+    Ok(run_scale_free_batch_async(std::slice::from_ref(alpha), radix, peer).await?[0])
+}
+
+/// run_scale_free_batch is run_scale_free_batch_async on the calling
+/// thread, returning our share of each product.
+pub fn run_scale_free_batch<T>(
+    alphas: &[scalars::Scalar],
+    radix: Radix,
+    mut peer: Framed<T>,
+) -> Result<Vec<scalars::Scalar>>
 where
-    T: ReadWrite + HasTryClone + Send,
+    T: ReadWrite,
 {
-    let (tx_alpha, rx_alpha) = channel::<scalars::scalar>();
-    let mut neg_phis = std::sync::Arc::<[[u64; 4];32]>::new([[0u64;4];32]);
-    // There are no copies yet, so we can just make_mut to get a mutable ref
-    for neg_phi in std::sync::Arc::make_mut(&mut neg_phis).iter_mut() {
-        *neg_phi = scalars::random_scalar();
+    futures::executor::block_on(run_scale_free_batch_async(alphas, radix, &mut peer))
+}
+
+/// run_scale_free_batch_async multiplies each of alphas by the peer's one
+/// beta in a single batch of OTs, so a batch takes as many round trips as
+/// one multiplication. The peer must be running
+/// receiver::run_scale_free_batch_async for alphas.len() products.
+pub async fn run_scale_free_batch_async(
+    alphas: &[scalars::Scalar],
+    radix: Radix,
+    peer: &mut dyn Channel,
+) -> Result<Vec<scalars::Scalar>> {
+    let neg_phis: Zeroizing<Vec<scalars::Scalar>> = Zeroizing::new(
+        (0..alphas.len() * radix.digits())
+            .map(|_| scalars::Scalar::random())
+            .collect(),
+    );
+    let rows = alphas
+        .iter()
+        .zip(neg_phis.chunks_exact(radix.digits()))
+        .flat_map(|(alpha, neg_phis)| {
+            let mut alpha_doubles = Zeroizing::new(*alpha);
+            neg_phis
+                .iter()
+                .enumerate()
+                .map(move |(count, neg_phi)| {
+                    scale_free_row(&mut alpha_doubles, count, neg_phi, radix)
+                })
+        });
+    transfer_async(rows, radix, alphas.len(), peer).await?;
+    Ok(neg_phis.chunks_exact(radix.digits()).map(negated_sum).collect())
+}
+
+/// run_scale_free_extended_async is run_scale_free_async with each row
+/// offered in a 1-of-radix.entries() OT built from radix.bits() OTs drawn
+/// from pool, as in ot::one_of_n.
+pub async fn run_scale_free_extended_async(
+    alpha: &scalars::Scalar,
+    radix: Radix,
+    pool: &mut SenderPool,
+    peer: &mut dyn Channel,
+) -> Result<scalars::Scalar> {
+    let bits = radix.bits() as usize;
+    let pairs = pool.extend_async(radix.digits() * bits, peer).await?;
+    let neg_phis: Zeroizing<Vec<scalars::Scalar>> =
+        Zeroizing::new((0..radix.digits()).map(|_| scalars::Scalar::random()).collect());
+    let mut alpha_doubles = Zeroizing::new(*alpha);
+    for ((count, neg_phi), pairs) in neg_phis.iter().enumerate().zip(pairs.chunks_exact(bits)) {
+        let row = scale_free_row(&mut alpha_doubles, count, neg_phi, radix);
+        let es = one_of_n::encrypt(pairs, &row[..radix.entries()], xor_cipher)?;
+        peer.send_frame(Kind::OtCiphertexts, &es[..]).await?;
     }
+    Ok(negated_sum(&neg_phis[..]))
+}
+
+pub fn run_scale_free_stupid_parallel<T>(
+    radix: Radix,
+    peer: Framed<T>,
+) -> (Sender<scalars::Scalar>, scalars::Scalar, thread::JoinHandle<Result<()>>)
+where
+    T: 'static + ReadWrite + HasTryClone + Send,
+{
+    let (tx_alpha, rx_alpha) = channel::<scalars::Scalar>();
+    let neg_phis: Zeroizing<Vec<scalars::Scalar>> =
+        Zeroizing::new((0..radix.digits()).map(|_| scalars::Scalar::random()).collect());
+    // shared with the worker thread, and wiped when the last copy drops
+    let neg_phis = std::sync::Arc::new(neg_phis);
     let t = {
-        // MSB to LSB
-        let (tx_row, rx_row) = channel::<[[u64; 4]; 256]>();
-        let t = {
-            let mut peer_clone = peer.try_clone();
-            thread::spawn(move || {
-                let ctx = &secp256k1::Secp256k1::new();
-                for mut row in rx_row.iter().take(32) {
-                    protocol::ot::sender::run(&ctx, &mut row[..], xor_cipher, &mut peer_clone);
-                }
-            })
-        };
+        // LSB to MSB
+        let (tx_row, rx_row) = channel::<scalars::Table>();
+        let t = transfer(rx_row, radix, peer);
         // Now we increment the reference count via clone and pass to the worker thread
         let neg_phis = std::sync::Arc::clone(&neg_phis);
         thread::spawn(move || -> Option<()> {
-            let mut alpha : scalars::scalar = rx_alpha.recv().ok()?;
-            for (count, neg_phi) in neg_phis.iter().take(32).enumerate() {
-                scalars::non_constant_time_shift(&mut alpha, (count > 0) as u8);
-                let mut row = scalars::scalar_mul_by_256(&alpha);
-                scalars::assign_add(&mut row, &neg_phi);
-                tx_row.send(row).ok()?;
+            let mut alpha = Zeroizing::new(rx_alpha.recv().ok()?);
+            for (count, neg_phi) in neg_phis.iter().enumerate() {
+                tx_row.send(scale_free_row(&mut alpha, count, neg_phi, radix)).ok()?;
             }
             Some(())
         });
         t
     };
-    
+
 
     // Sum up and return
-    let mut neg_sigma_alpha = neg_phis[0];
-    for neg_phi in neg_phis.iter().skip(1) {
-        scalars::secp256k1_scalar_add_assign(&mut neg_sigma_alpha, &neg_phi);
-    }
-    scalars::secp256k1_scalar_negate(&mut neg_sigma_alpha);
-    (tx_alpha, neg_sigma_alpha, t)
+    (tx_alpha, negated_sum(&neg_phis[..]), t)
 }
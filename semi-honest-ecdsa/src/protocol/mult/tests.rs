@@ -1,79 +1,48 @@
+use crate::error::Result;
 use crate::protocol;
-use crate::test::Bencher;
+use crate::wire::Framed;
 use crate::*;
-use std::sync::mpsc::*;
 
 use std::os::unix::net::UnixStream;
 use std::thread;
 
 fn verify_shares(
-    a: scalars::scalar,
-    b: scalars::scalar,
-    mut s_a: scalars::scalar,
-    s_b: scalars::scalar,
+    a: scalars::Scalar,
+    b: scalars::Scalar,
+    s_a: scalars::Scalar,
+    s_b: scalars::Scalar,
 ) {
-    let ctx = &secp256k1::Secp256k1::new();
-    let mut a =
-        secp256k1::SecretKey::from_slice(ctx, &crate::scalars::bytes_from_scalar(&a)).unwrap();
-    let b = secp256k1::SecretKey::from_slice(ctx, &crate::scalars::bytes_from_scalar(&b)).unwrap();
-    crate::scalars::secp256k1_scalar_add_assign(&mut s_a, &s_b);
-    let s_a =
-        secp256k1::SecretKey::from_slice(ctx, &crate::scalars::bytes_from_scalar(&s_a)).unwrap();
-    a.mul_assign(ctx, &b);
-    assert_eq!(a, s_a);
+    // check against secp256k1's own arithmetic
+    let a = a.secret_key().unwrap();
+    let ab = a.mul_tweak(&b.tweak()).unwrap();
+    assert_eq!(scalars::Scalar::from(ab), s_a + s_b);
 }
-fn test_mult<S: 'static, R: 'static>(sender: S, receiver: R)
+fn test_mult<S, R>(sender: S, receiver: R)
 where
-    S: Fn(&scalars::scalar, UnixStream) -> (scalars::scalar, thread::JoinHandle<()>),
-    R: Fn(&scalars::scalar, UnixStream) -> thread::JoinHandle<scalars::scalar>,
+    S: Fn(
+        &scalars::Scalar,
+        Framed<UnixStream>,
+    ) -> (scalars::Scalar, thread::JoinHandle<Result<()>>),
+    R: Fn(&scalars::Scalar, Framed<UnixStream>) -> thread::JoinHandle<Result<scalars::Scalar>>,
 {
-    let (mut sock1, mut sock2) = UnixStream::pair().unwrap();
+    let (sock1, sock2) = UnixStream::pair().unwrap();
     let h2 = {
-        let secret = crate::scalars::random_scalar();
-        let share = sender(&secret, sock1).0;
+        let secret = scalars::Scalar::random();
+        let share = sender(&secret, Framed::new(sock1, 0)).0;
         (secret, share)
     };
     let h1 = {
-        let secret = crate::scalars::random_scalar();
-        let share = receiver(&secret, sock2);
+        let secret = scalars::Scalar::random();
+        let share = receiver(&secret, Framed::new(sock2, 0));
         (secret, share)
     };
 
-    let (mut a, mut s_a) = h2;
-    let (mut b, th) = h1;
-    let mut s_b = th.join().unwrap();
+    let (a, s_a) = h2;
+    let (b, th) = h1;
+    let s_b = th.join().unwrap().unwrap();
     verify_shares(a, b, s_a, s_b);
 }
 
-fn bench_setup<R: 'static>(receiver: R) -> UnixStream
-where
-    R: Send,
-    R: Fn(&scalars::scalar, UnixStream) -> thread::JoinHandle<scalars::scalar>,
-{
-    let (mut sock1, mut sock2) = UnixStream::pair().unwrap();
-
-    thread::spawn(move || {
-        let secret = crate::scalars::random_scalar();
-        let share = receiver(&secret, sock2).join().unwrap();
-    });
-
-    sock1
-}
-fn bench_mult<S: 'static, R: 'static>(sender: S, receiver: R, b: &mut Bencher)
-where
-    S: Fn(&scalars::scalar, UnixStream) -> (scalars::scalar, thread::JoinHandle<()>),
-    R: Fn(&scalars::scalar, UnixStream) -> thread::JoinHandle<scalars::scalar>,
-    R: Send + Sync + Clone,
-    S: Send,
-{
-    b.iter(|| {
-        let sock = bench_setup(receiver.clone());
-        let secret = crate::scalars::random_scalar();
-        let (share, th) = sender(&secret, sock);
-        //th.join().unwrap();
-    });
-}
-
 #[test]
 fn mult_works() {
     test_mult(protocol::mult::sender::run, protocol::mult::receiver::run);
@@ -81,26 +50,129 @@ fn mult_works() {
 
 #[test]
 fn scale_free() {
-    test_mult(
-        protocol::mult::sender::run_scale_free,
-        protocol::mult::receiver::run_scale_free,
-    );
+    for bits in 1..=8 {
+        let radix = protocol::mult::Radix::new(bits).unwrap();
+        test_mult(
+            move |a, p| protocol::mult::sender::run_scale_free(a, radix, p),
+            move |b, p| protocol::mult::receiver::run_scale_free(b, radix, p),
+        );
+    }
 }
 
-#[bench]
-fn bench_scaled_mult(b: &mut Bencher) {
-    bench_mult(
-        protocol::mult::sender::run,
-        (protocol::mult::receiver::run),
-        b,
-    );
+#[test]
+fn radix_digits() {
+    use protocol::mult::Radix;
+    assert!(Radix::new(0).is_err() && Radix::new(9).is_err());
+    let b = scalars::Scalar::random();
+    for bits in 1..=8 {
+        let radix = Radix::new(bits).unwrap();
+        let digits = radix.digits_of(&b);
+        assert_eq!(digits.len(), radix.digits());
+        assert!(digits.iter().all(|d| (*d as usize) < radix.entries()));
+        let sum: scalars::Scalar = digits
+            .iter()
+            .enumerate()
+            .map(|(i, d)| scalars::Scalar::from_u64(*d as u64).mul_pow2(i as u32 * bits as u32))
+            .sum();
+        assert_eq!(sum, b);
+    }
+    let digits = Radix::default().digits_of(&b);
+    let mut bytes = b.to_bytes();
+    bytes.reverse();
+    assert_eq!(&digits[..], &bytes[..]);
 }
 
-#[bench]
-fn bench_scale_free_mult(b: &mut Bencher) {
-    bench_mult(
-        protocol::mult::sender::run_scale_free,
-        (protocol::mult::receiver::run_scale_free),
-        b,
-    );
+#[test]
+fn state_machines_without_sockets() {
+    use protocol::mult::receiver::Choose;
+    use protocol::mult::sender::Transfer;
+    use protocol::mult::{Radix, Step};
+    let ctx = &secp256k1::Secp256k1::new();
+    let (a, b) = (scalars::Scalar::random(), scalars::Scalar::random());
+
+    // the sender's rows, as run_scale_free builds them
+    let neg_phis: Vec<scalars::Scalar> = (0..32).map(|_| scalars::Scalar::random()).collect();
+    let mut alpha_doubles = a;
+    let rows = neg_phis.iter().enumerate().map(|(count, neg_phi)| {
+        scalars::shift(&mut alpha_doubles, (count > 0) as u8);
+        let mut row = scalars::scalar_mul_by_256(&alpha_doubles);
+        scalars::assign_add(&mut row[..], neg_phi);
+        row
+    });
+    let s_a = -neg_phis.iter().sum::<scalars::Scalar>();
+
+    let mut choices = b.to_bytes();
+    choices.reverse();
+    let mut chooser = Choose::new(&choices[..], Radix::default()).unwrap();
+    let (mut transfer, s) = Transfer::start(ctx, Radix::default(), rows).unwrap();
+    // one S for the whole multiplication, answered with every R at once
+    assert_eq!(s.len(), 1);
+    let (next, rs) = chooser.step(ctx, s.into_iter().next().unwrap()).unwrap();
+    chooser = match next {
+        Step::Next(c) => c,
+        Step::Done(_) => panic!("receiver done before any OT"),
+    };
+    assert_eq!(rs.len(), 32);
+    let mut rs = rs.into_iter();
+    let vs = loop {
+        let (next, es) = transfer.step(ctx, rs.next().unwrap()).unwrap();
+        assert_eq!(es.len(), 1);
+        let (done, out) = chooser.step(ctx, es.into_iter().next().unwrap()).unwrap();
+        assert!(out.is_empty());
+        match (next, done) {
+            (Step::Next(t), Step::Next(c)) => {
+                transfer = t;
+                chooser = c;
+            }
+            (Step::Done(()), Step::Done(vs)) => break vs,
+            _ => panic!("sender and receiver disagree on the number of OTs"),
+        }
+    };
+    assert!(rs.next().is_none());
+
+    let s_b = vs.iter().sum();
+    verify_shares(a, b, s_a, s_b);
+}
+
+#[test]
+fn extended_scale_free() {
+    use crate::protocol::ot::extension::{ReceiverPool, SenderPool};
+    use protocol::mult::Radix;
+    let (sock1, sock2) = UnixStream::pair().unwrap();
+    let radixes = [8, 1, 3];
+    let h = thread::spawn(move || {
+        let ctx = &secp256k1::Secp256k1::new();
+        let mut peer = Framed::new(sock2, 0);
+        let mut pool = ReceiverPool::setup(ctx, &mut peer).unwrap();
+        // one pool serves many multiplications
+        let mut shares = vec![];
+        for bits in radixes.iter() {
+            let b = scalars::Scalar::random();
+            let radix = Radix::new(*bits).unwrap();
+            let s_b = futures::executor::block_on(
+                protocol::mult::receiver::run_scale_free_extended_async(
+                    &b, radix, &mut pool, &mut peer,
+                ),
+            )
+            .unwrap();
+            shares.push((b, s_b));
+        }
+        shares
+    });
+    let ctx = &secp256k1::Secp256k1::new();
+    let mut peer = Framed::new(sock1, 0);
+    let mut pool = SenderPool::setup(ctx, &mut peer).unwrap();
+    let mut shares = vec![];
+    for bits in radixes.iter() {
+        let a = scalars::Scalar::random();
+        let radix = Radix::new(*bits).unwrap();
+        let s_a = futures::executor::block_on(
+            protocol::mult::sender::run_scale_free_extended_async(&a, radix, &mut pool, &mut peer),
+        )
+        .unwrap();
+        shares.push((a, s_a));
+    }
+    for ((a, s_a), (b, s_b)) in shares.into_iter().zip(h.join().unwrap()) {
+        verify_shares(a, b, s_a, s_b);
+    }
 }
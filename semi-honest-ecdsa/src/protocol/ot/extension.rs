@@ -0,0 +1,242 @@
+//! IKNP OT extension: KAPPA base OTs run once per pair of peers, after which
+//! any number of 1-of-2 OTs of 32 byte keys cost only hashing.
+//!
+//! The base OTs run the other way round. The extension sender picks s and
+//! learns k^{s_i}_i of each of the extension receiver's seed pairs
+//! (k^0_i, k^1_i). To extend m OTs with choice bits r, the receiver sends
+//! u_i = G(k^0_i) ^ G(k^1_i) ^ r for each i, and the sender computes
+//! q_i = G(k^{s_i}_i) ^ s_i u_i = t_i ^ s_i r, where t_i = G(k^0_i). Row j
+//! of q is t_j ^ r_j s, so the sender's keys for OT j are H(j, q_j) and
+//! H(j, q_j ^ s), and the receiver's is H(j, t_j), the one it chose.
+//!
+//! This is the semi-honest protocol. A malicious receiver could send
+//! inconsistent columns and learn s, which KOS's consistency check rules
+//! out; it is not needed in our model.
+use super::{receiver, sender};
+use crate::error::{Error, Result};
+use crate::util::*;
+use crate::wire::{Channel, Kind, MAX_PAYLOAD};
+
+/// KAPPA is the number of base OTs, and the bits in a row of q.
+pub const KAPPA: usize = 128;
+
+/// BATCH is the most OTs one round of extension carries, so the columns fit
+/// in one frame.
+pub const BATCH: usize = MAX_PAYLOAD / KAPPA * 8;
+
+/// Row is one row of t or q, a bit from each column.
+type Row = [u8; KAPPA / 8];
+
+/// prg stretches seed into the m bit column for a batch.
+fn prg(seed: &[u8; 32], batch: u64, m: usize) -> Zeroizing<Vec<u8>> {
+    let mut col = Zeroizing::new(Vec::with_capacity(m.div_ceil(256) * 32));
+    for block in 0..m.div_ceil(256) as u64 {
+        let mut h = Sha256::new()
+            .chain_update(b"ObliviousTransferExtensionPrg")
+            .chain_update(&seed[..])
+            .chain_update(batch.to_be_bytes())
+            .chain_update(block.to_be_bytes())
+            .finalize();
+        col.extend_from_slice(h.as_slice());
+        h.as_mut_slice().zeroize();
+    }
+    col.truncate(m.div_ceil(8));
+    col
+}
+
+/// bit is bit j of a packed bit string.
+fn bit(b: &[u8], j: usize) -> u8 {
+    (b[j / 8] >> (j % 8)) & 1
+}
+
+/// rows transposes KAPPA columns of m bits into m rows of KAPPA bits.
+fn rows(cols: &[Zeroizing<Vec<u8>>], m: usize) -> Zeroizing<Vec<Row>> {
+    let mut rows = Zeroizing::new(vec![[0u8; KAPPA / 8]; m]);
+    for (i, col) in cols.iter().enumerate() {
+        for (j, row) in rows.iter_mut().enumerate() {
+            row[i / 8] |= bit(col, j) << (i % 8);
+        }
+    }
+    rows
+}
+
+/// key hashes row j of a batch into the key for OT j.
+fn key(batch: u64, j: usize, row: &Row) -> [u8; 32] {
+    let mut h = Sha256::new()
+        .chain_update(b"ObliviousTransferExtensionKey")
+        .chain_update(batch.to_be_bytes())
+        .chain_update((j as u64).to_be_bytes())
+        .chain_update(&row[..])
+        .finalize();
+    let mut k = [0u8; 32];
+    k.clone_from_slice(h.as_slice());
+    h.as_mut_slice().zeroize();
+    k
+}
+
+/// SenderPool is the extension sender's side of the base OTs with one peer.
+/// It yields key pairs, of which the peer's ReceiverPool learns one each.
+pub struct SenderPool {
+    s: Zeroizing<Row>,
+    seeds: Zeroizing<Vec<[u8; 32]>>,
+    batch: u64,
+}
+
+impl SenderPool {
+    /// setup is setup_async driven to completion on the calling thread.
+    pub fn setup<C>(ctx: &secp256k1::Secp256k1<C>, peer: &mut dyn Channel) -> Result<SenderPool>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        futures::executor::block_on(SenderPool::setup_async(ctx, peer))
+    }
+
+    /// setup_async runs the base OTs with a peer running
+    /// ReceiverPool::setup_async.
+    pub async fn setup_async<C>(
+        ctx: &secp256k1::Secp256k1<C>,
+        peer: &mut dyn Channel,
+    ) -> Result<SenderPool>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        let s: Zeroizing<Row> = Zeroizing::new(rand::random());
+        let mut seeds = Zeroizing::new(Vec::with_capacity(KAPPA));
+        for i in 0..KAPPA {
+            let k = receiver::run_async(ctx, bit(&s[..], i), 2, xor_decipher_bytes, peer).await?;
+            seeds.push(k);
+        }
+        Ok(SenderPool { s, seeds, batch: 0 })
+    }
+
+    /// extend is extend_async driven to completion on the calling thread.
+    pub fn extend(
+        &mut self,
+        m: usize,
+        peer: &mut dyn Channel,
+    ) -> Result<Zeroizing<Vec<[[u8; 32]; 2]>>> {
+        futures::executor::block_on(self.extend_async(m, peer))
+    }
+
+    /// extend_async draws m OTs, returning the key pair of each in order.
+    /// The peer must be extending by the same number at the same point.
+    pub async fn extend_async(
+        &mut self,
+        m: usize,
+        peer: &mut dyn Channel,
+    ) -> Result<Zeroizing<Vec<[[u8; 32]; 2]>>> {
+        let mut pairs = Zeroizing::new(Vec::with_capacity(m));
+        let mut left = m;
+        while left > 0 {
+            let n = left.min(BATCH);
+            let bytes = n.div_ceil(8);
+            let mut us = vec![0u8; KAPPA * bytes];
+            peer.recv_frame(Kind::OtExtension, &mut us[..]).await?;
+            let cols: Vec<Zeroizing<Vec<u8>>> = us
+                .chunks_exact(bytes)
+                .zip(self.seeds.iter())
+                .enumerate()
+                .map(|(i, (u, seed))| {
+                    // q_i = G(k^{s_i}_i) ^ s_i u_i, masked rather than branched on s_i
+                    let mask = bit(&self.s[..], i).wrapping_neg();
+                    let mut q = prg(seed, self.batch, n);
+                    for (w, b) in q.iter_mut().zip(u.iter()) {
+                        *w ^= b & mask;
+                    }
+                    q
+                })
+                .collect();
+            for (j, q) in rows(&cols[..], n).iter().enumerate() {
+                let mut q_s = *q;
+                for (w, b) in q_s.iter_mut().zip(self.s.iter()) {
+                    *w ^= b;
+                }
+                pairs.push([key(self.batch, j, q), key(self.batch, j, &q_s)]);
+                q_s.zeroize();
+            }
+            self.batch += 1;
+            left -= n;
+        }
+        Ok(pairs)
+    }
+}
+
+/// ReceiverPool is the extension receiver's side of the base OTs with one
+/// peer. It yields the key chosen from each of the peer's SenderPool pairs.
+pub struct ReceiverPool {
+    seeds: Zeroizing<Vec<[[u8; 32]; 2]>>,
+    batch: u64,
+}
+
+impl ReceiverPool {
+    /// setup is setup_async driven to completion on the calling thread.
+    pub fn setup<C>(ctx: &secp256k1::Secp256k1<C>, peer: &mut dyn Channel) -> Result<ReceiverPool>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        futures::executor::block_on(ReceiverPool::setup_async(ctx, peer))
+    }
+
+    /// setup_async runs the base OTs with a peer running
+    /// SenderPool::setup_async.
+    pub async fn setup_async<C>(
+        ctx: &secp256k1::Secp256k1<C>,
+        peer: &mut dyn Channel,
+    ) -> Result<ReceiverPool>
+    where
+        C: secp256k1::Signing + secp256k1::Verification,
+    {
+        let seeds: Zeroizing<Vec<[[u8; 32]; 2]>> =
+            Zeroizing::new((0..KAPPA).map(|_| [rand::random(), rand::random()]).collect());
+        for pair in seeds.iter() {
+            sender::run_async(ctx, &pair[..], xor_cipher, peer).await?;
+        }
+        Ok(ReceiverPool { seeds, batch: 0 })
+    }
+
+    /// extend is extend_async driven to completion on the calling thread.
+    pub fn extend(
+        &mut self,
+        choices: &[u8],
+        peer: &mut dyn Channel,
+    ) -> Result<Zeroizing<Vec<[u8; 32]>>> {
+        futures::executor::block_on(self.extend_async(choices, peer))
+    }
+
+    /// extend_async draws an OT for each choice bit, 0 or 1, returning the
+    /// chosen key of each in order.
+    pub async fn extend_async(
+        &mut self,
+        choices: &[u8],
+        peer: &mut dyn Channel,
+    ) -> Result<Zeroizing<Vec<[u8; 32]>>> {
+        if choices.iter().any(|c| *c > 1) {
+            return Err(Error::ProtocolAbort("OT extension choices are bits"));
+        }
+        let mut keys = Zeroizing::new(Vec::with_capacity(choices.len()));
+        for chunk in choices.chunks(BATCH) {
+            let n = chunk.len();
+            let mut r = Zeroizing::new(vec![0u8; n.div_ceil(8)]);
+            for (j, c) in chunk.iter().enumerate() {
+                r[j / 8] |= c << (j % 8);
+            }
+            let mut us = Vec::with_capacity(KAPPA * r.len());
+            let mut cols = Vec::with_capacity(KAPPA);
+            for seed in self.seeds.iter() {
+                // u_i = t_i ^ G(k^1_i) ^ r
+                let t = prg(&seed[0], self.batch, n);
+                let g1 = prg(&seed[1], self.batch, n);
+                for ((a, b), c) in t.iter().zip(g1.iter()).zip(r.iter()) {
+                    us.push(a ^ b ^ c);
+                }
+                cols.push(t);
+            }
+            peer.send_frame(Kind::OtExtension, &us[..]).await?;
+            for (j, t) in rows(&cols[..], n).iter().enumerate() {
+                keys.push(key(self.batch, j, t));
+            }
+            self.batch += 1;
+        }
+        Ok(keys)
+    }
+}
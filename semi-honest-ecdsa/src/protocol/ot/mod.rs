@@ -1,4 +1,77 @@
+pub mod extension;
+pub mod one_of_n;
 pub mod receiver;
 pub mod sender;
 #[cfg(test)]
 mod tests;
+use crate::error::{Error, Result};
+use crate::wire::{Channel, Kind};
+use secp256k1::PublicKey;
+
+/// Msg is one OT message, as the state machines in `sender::protocol` and
+/// `receiver::protocol` consume and emit them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Msg {
+    /// the sender's S = yG
+    S(PublicKey),
+    /// the receiver's R = cT + xG
+    R(PublicKey),
+    /// the sender's ciphertexts, 32 bytes for each message in order
+    Ciphertexts(Vec<u8>),
+}
+
+impl Msg {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Msg::S(_) => Kind::OtS,
+            Msg::R(_) => Kind::OtR,
+            Msg::Ciphertexts(_) => Kind::OtCiphertexts,
+        }
+    }
+
+    /// len is the length of a `kind` message in an OT of n messages.
+    pub fn len(kind: Kind, n: usize) -> usize {
+        match kind {
+            Kind::OtCiphertexts => n * 32,
+            _ => 33,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Msg::S(p) | Msg::R(p) => p.serialize().to_vec(),
+            Msg::Ciphertexts(es) => es.clone(),
+        }
+    }
+
+    pub fn from_bytes(kind: Kind, b: &[u8]) -> Result<Msg> {
+        match kind {
+            Kind::OtS | Kind::OtR => {
+                let p = PublicKey::from_slice(b).map_err(|_| Error::InvalidPoint)?;
+                Ok(if kind == Kind::OtS {
+                    Msg::S(p)
+                } else {
+                    Msg::R(p)
+                })
+            }
+            Kind::OtCiphertexts if b.len().is_multiple_of(32) => Ok(Msg::Ciphertexts(b.to_vec())),
+            _ => Err(Error::ProtocolAbort("not an OT message")),
+        }
+    }
+}
+
+/// send_msgs frames each message a state machine emitted.
+pub(crate) async fn send_msgs(peer: &mut dyn Channel, out: Vec<Msg>) -> Result<()> {
+    for msg in out {
+        peer.send_frame(msg.kind(), &msg.to_bytes()[..]).await?;
+    }
+    Ok(())
+}
+
+/// recv_msg reads the next message, which must be a `kind` message of an OT
+/// of n messages.
+pub(crate) async fn recv_msg(peer: &mut dyn Channel, kind: Kind, n: usize) -> Result<Msg> {
+    let mut buf = vec![0u8; Msg::len(kind, n)];
+    peer.recv_frame(kind, &mut buf[..]).await?;
+    Msg::from_bytes(kind, &buf[..])
+}
@@ -0,0 +1,159 @@
+//! 1-of-N OT from ⌈log2 N⌉ 1-of-2 OTs, as in appendix A of the paper.
+//!
+//! The sender draws a key pair (k^0_j, k^1_j) for each bit j of an index
+//! and encrypts entry i under the key of every pair picked by i's bits. It
+//! sends all N ciphertexts, then offers each key pair in a 1-of-2 OT, from
+//! which the receiver takes the keys picked by the bits of its choice.
+//!
+//! Each layer of the nested encryption XORs in a pad hashed from its key,
+//! its bit and the entry index. XORing in the keys themselves would let the
+//! receiver cancel them, e.g. e_0 ^ e_1 ^ e_2 ^ e_3 = m_0 ^ m_1 ^ m_2 ^ m_3.
+use super::{receiver, recv_msg, send_msgs, sender, Msg};
+use crate::error::{Error, Result};
+use crate::util::*;
+use crate::wire::{Channel, Kind, MAX_PAYLOAD};
+use std::io::Write;
+
+/// bits is ⌈log2 n⌉, the number of 1-of-2 OTs a 1-of-n OT takes.
+pub fn bits(n: usize) -> usize {
+    (usize::BITS - n.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// pad is the XOR of every layer's pad for entry i, keys[j] being the key
+/// for bit j of i.
+fn pad(keys: &[[u8; 32]], i: usize) -> Zeroizing<[u8; 32]> {
+    let mut p = Zeroizing::new([0u8; 32]);
+    for (j, k) in keys.iter().enumerate() {
+        let mut h = Sha256::new()
+            .chain_update(b"OneOfNObliviousTransfer")
+            .chain_update((j as u32).to_be_bytes())
+            .chain_update((i as u64).to_be_bytes())
+            .chain_update(&k[..])
+            .finalize();
+        for (w, b) in p.iter_mut().zip(h.iter()) {
+            *w ^= b;
+        }
+        h.as_mut_slice().zeroize();
+    }
+    p
+}
+
+/// check_n fails for an n with nothing to choose from or too many
+/// ciphertexts to fit in a frame.
+fn check_n(n: usize) -> Result<()> {
+    if n == 0 || n > MAX_PAYLOAD / 32 {
+        return Err(Error::ProtocolAbort("bad number of OT messages"));
+    }
+    Ok(())
+}
+
+/// send is send_async driven to completion on the calling thread.
+pub fn send<T, E, M>(
+    ctx: &secp256k1::Secp256k1<T>,
+    msg: &[M],
+    enc: E,
+    peer: &mut dyn Channel,
+) -> Result<()>
+where
+    T: secp256k1::Verification + secp256k1::Signing,
+    E: Fn(&M, &[u8], &mut dyn Write) -> std::io::Result<()>,
+{
+    futures::executor::block_on(send_async(ctx, msg, enc, peer))
+}
+
+/// send_async offers every message in msg, of which the receiver learns
+/// exactly one. enc encrypts a message under a 32 byte pad, as for
+/// sender::run.
+pub async fn send_async<T, E, M>(
+    ctx: &secp256k1::Secp256k1<T>,
+    msg: &[M],
+    enc: E,
+    peer: &mut dyn Channel,
+) -> Result<()>
+where
+    T: secp256k1::Verification + secp256k1::Signing,
+    E: Fn(&M, &[u8], &mut dyn Write) -> std::io::Result<()>,
+{
+    check_n(msg.len())?;
+    // k^b_j <- {0,1}^256
+    let pairs: Zeroizing<Vec<[[u8; 32]; 2]>> =
+        Zeroizing::new((0..bits(msg.len())).map(|_| [rand::random(), rand::random()]).collect());
+    let es = encrypt(&pairs[..], msg, enc)?;
+    send_msgs(peer, vec![Msg::Ciphertexts(es)]).await?;
+    for pair in pairs.iter() {
+        sender::run_async(ctx, &pair[..], xor_cipher, peer).await?;
+    }
+    Ok(())
+}
+
+/// receive is receive_async driven to completion on the calling thread.
+pub fn receive<T, M, D>(
+    ctx: &secp256k1::Secp256k1<T>,
+    choice: usize,
+    n: usize,
+    decrypt: D,
+    peer: &mut dyn Channel,
+) -> Result<M>
+where
+    T: secp256k1::Verification + secp256k1::Signing,
+    D: Fn(&[u8], &[u8]) -> M,
+{
+    futures::executor::block_on(receive_async(ctx, choice, n, decrypt, peer))
+}
+
+/// receive_async picks message `choice` of the n the sender offers.
+/// decrypt takes a 32 byte pad and ciphertext, as for receiver::run.
+pub async fn receive_async<T, M, D>(
+    ctx: &secp256k1::Secp256k1<T>,
+    choice: usize,
+    n: usize,
+    decrypt: D,
+    peer: &mut dyn Channel,
+) -> Result<M>
+where
+    T: secp256k1::Verification + secp256k1::Signing,
+    D: Fn(&[u8], &[u8]) -> M,
+{
+    check_n(n)?;
+    if choice >= n {
+        return Err(Error::ProtocolAbort("OT choice out of range"));
+    }
+    let es = match recv_msg(peer, Kind::OtCiphertexts, n).await? {
+        Msg::Ciphertexts(es) => es,
+        _ => return Err(Error::ProtocolAbort("OT receiver expected ciphertexts")),
+    };
+    let mut keys = Zeroizing::new(Vec::with_capacity(bits(n)));
+    for j in 0..bits(n) {
+        let bit = ((choice >> j) & 1) as u8;
+        keys.push(receiver::run_async(ctx, bit, 2, xor_decipher_bytes, peer).await?);
+    }
+    Ok(open(&keys[..], choice, &es[..], decrypt))
+}
+
+/// encrypt encrypts every message in msg, each under the keys pairs[j][b]
+/// picked by the bits b of its index. There must be a pair for every bit.
+pub(crate) fn encrypt<E, M>(pairs: &[[[u8; 32]; 2]], msg: &[M], enc: E) -> Result<Vec<u8>>
+where
+    E: Fn(&M, &[u8], &mut dyn Write) -> std::io::Result<()>,
+{
+    assert!(bits(msg.len()) <= pairs.len());
+    let mut keys = Zeroizing::new(vec![[0u8; 32]; pairs.len()]);
+    let mut es = Vec::with_capacity(msg.len() * 32);
+    for (i, m) in msg.iter().enumerate() {
+        for (j, (k, pair)) in keys.iter_mut().zip(pairs.iter()).enumerate() {
+            *k = pair[(i >> j) & 1];
+        }
+        enc(m, &pad(&keys[..], i)[..], &mut es)?;
+    }
+    Ok(es)
+}
+
+/// open decrypts entry `choice` of the ciphertexts es, keys[j] being the
+/// key for bit j of choice.
+pub(crate) fn open<M, D>(keys: &[[u8; 32]], choice: usize, es: &[u8], decrypt: D) -> M
+where
+    D: Fn(&[u8], &[u8]) -> M,
+{
+    let (e, _) = receiver::select(es, choice);
+    decrypt(&pad(keys, choice)[..], &e[..])
+}
@@ -1,153 +1,199 @@
+use super::Msg;
+use crate::error::{Error, Result};
+use crate::scalars::Scalar;
 use crate::util::*;
-mod protocol {
+use crate::wire::{Channel, Kind};
+
+/// protocol is the receiver as a state machine: each state takes the
+/// sender's message and returns the next state and what to send, doing no
+/// I/O of its own.
+#[allow(non_camel_case_types, clippy::new_without_default)]
+pub mod protocol {
     use super::*;
-    pub mod start {
-        use super::*;
-        pub struct start<'a>(&'a mut dyn ReadWrite);
-        pub struct started<'a>(&'a mut dyn ReadWrite);
-        impl<'a> start<'a> {
-            pub fn new(a: &'a mut dyn ReadWrite) -> start<'a> {
-                start(a)
-            }
-            pub fn next(self) -> started<'a> {
-                started(self.0)
-            }
-        }
-        impl<'a> started<'a> {
-            pub fn get_stream<'b>(a: super::get_s::get_s<'a, 'b>) -> &'b mut dyn ReadWrite {
-                (a.0).0
-            }
-        }
+
+    /// start is a receiver waiting for S.
+    pub struct start {
+        x: Zeroizing<Scalar>,
+        choice: u8,
+    }
+
+    /// sent_r is a receiver which has sent R and waits for the ciphertexts.
+    pub struct sent_r {
+        k: Zeroizing<[u8; 32]>,
+        choice: u8,
     }
-    pub mod get_s {
-        use super::*;
-        pub struct get_s<'a, 'b>(pub &'b mut super::start::started<'a>);
-        pub struct got_s<'a>(&'a mut dyn ReadWrite);
-        impl<'a, 'b> get_s<'a, 'b> {
-            pub fn next<C>(self, ctx: &secp256k1::Secp256k1<C>) -> (PublicKey, got_s<'b>)
-            where
-                C: secp256k1::Signing,
-            {
-                let r: &'b mut dyn ReadWrite = super::start::started::get_stream(self);
-                let mut buffer = [0u8; 33];
-                match r.read_exact(&mut buffer) {
-                    Ok(_) => (),
-                    Err(a) => panic!("receiver reading S failed with {:?}", a),
-                };
-                match PublicKey::from_slice(ctx, &buffer) {
-                    Ok(s) => (s, got_s(r)),
-                    Err(e) => panic!("receiver taking S value failed with {:?}", e),
-                }
+
+    /// got_s is a receiver which has S, and can choose any number of
+    /// times against it, each choice for its own index.
+    pub struct got_s {
+        s: PublicKey,
+        // T = Oracle(S)
+        t: PublicKey,
+    }
+
+    impl start {
+        pub fn new(choice: u8) -> start {
+            start {
+                x: Zeroizing::new(Scalar::random()),
+                choice,
             }
         }
 
-        impl<'a> got_s<'a> {
-            pub fn get_stream<'b>(a: super::send_r::send_r<'a, 'b>) -> &'b mut dyn ReadWrite {
-                (a.0).0
-            }
+        /// step takes S and sends R = cT + xG, where T = Oracle(S) and c is
+        /// our choice.
+        pub fn step<C>(
+            self,
+            ctx: &secp256k1::Secp256k1<C>,
+            incoming: Msg,
+        ) -> Result<(sent_r, Vec<Msg>)>
+        where
+            C: secp256k1::Signing + secp256k1::Verification,
+        {
+            got_s::new(ctx, incoming)?.choose_with(ctx, 0, self.x, self.choice)
         }
     }
 
-    pub mod send_r {
-        use super::*;
-        pub struct send_r<'a, 'b>(pub &'b mut super::get_s::got_s<'a>);
-        pub struct sent_r<'a>(&'a mut dyn ReadWrite);
-        impl<'a, 'b> send_r<'a, 'b> {
-            pub fn next(self, r: &PublicKey) -> sent_r<'b> {
-                let w: &'b mut dyn ReadWrite = super::get_s::got_s::get_stream(self);
-                let b: [u8; 33] = r.serialize();
-                w.write_all(&b).unwrap();
-                w.flush();
-                sent_r(w)
+    impl got_s {
+        /// new takes S, computing T = Oracle(S) once for every choice.
+        pub fn new<C>(ctx: &secp256k1::Secp256k1<C>, incoming: Msg) -> Result<got_s>
+        where
+            C: secp256k1::Signing + secp256k1::Verification,
+        {
+            let s = match incoming {
+                Msg::S(s) => s,
+                _ => return Err(Error::ProtocolAbort("OT receiver expected S")),
+            };
+            // check s != G
+            if Scalar::ONE.base_point(ctx)? == s {
+                return Err(Error::InvalidPoint);
             }
+            Ok(got_s { s, t: oracle(&s)? })
         }
 
-        impl<'a> sent_r<'a> {
-            pub fn get_stream<'b>(a: super::get_es::get_es<'a, 'b>) -> &'b mut dyn ReadWrite {
-                (a.0).0
-            }
+        /// choose sends R for OT `index` of a batch sharing S, which the
+        /// sender must answer with the same index.
+        pub fn choose<C>(
+            &self,
+            ctx: &secp256k1::Secp256k1<C>,
+            index: u32,
+            choice: u8,
+        ) -> Result<(sent_r, Vec<Msg>)>
+        where
+            C: secp256k1::Signing + secp256k1::Verification,
+        {
+            self.choose_with(ctx, index, Zeroizing::new(Scalar::random()), choice)
         }
-    }
 
-    pub mod get_es {
-        use super::*;
-        pub struct get_es<'a, 'b>(pub &'b mut super::send_r::sent_r<'a>);
-        pub struct got_ciphertext(pub Vec<u8>);
-        impl<'a, 'b> get_es<'a, 'b> {
-            pub fn next<C>(self, ctx: &secp256k1::Secp256k1<C>, choice: u8) -> got_ciphertext {
-                let r: &'b mut dyn ReadWrite = super::send_r::sent_r::get_stream(self);
-                // MUST BE CONSTANT TIME
-/*
-		{
-			peer.read_exact(&mut unsafe{std::mem::transmute::<[[u64;4];256], [u8; 8192]>(row)}[..]);
-			for r in row.iter_mut() {
-				for v in r.iter_mut() {
-					*v = v.from_be();
-				}
-			}
-		} */
+        fn choose_with<C>(
+            &self,
+            ctx: &secp256k1::Secp256k1<C>,
+            index: u32,
+            x: Zeroizing<Scalar>,
+            choice: u8,
+        ) -> Result<(sent_r, Vec<Msg>)>
+        where
+            C: secp256k1::Signing + secp256k1::Verification,
+        {
+            let t = self.t;
+            // R = cT + xG, taken as (c + 256)T + xG - 256T so that T is
+            // never multiplied by 0 and nothing branches on c. Both
+            // multiplications of T and xG are constant time in the scalar.
+            let c = Scalar::from_u64(choice as u64 + 256);
+            let neg_256 = -Scalar::from_u64(256);
+            let t = PublicKey::combine_keys(&[
+                &c.mul_point(ctx, &t)?,
+                &x.base_point(ctx)?,
+                &neg_256.mul_point(ctx, &t)?,
+            ])?;
 
-                let mut results = Vec::with_capacity(256);
-                for t in 0..256 {
-                    let mut buffer = vec![0u8; 32];
-                    match r.read_exact(buffer.as_mut_slice()) {
-                        Ok(_) => (),
-                        Err(a) => panic!("read_exact failed in receiver get_es with {:?}", a),
-                    }
-                    results.push(buffer);
-                }
+            let h = Sha256::new()
+                .chain_update(index.to_be_bytes())
+                .chain_update(&self.s.serialize()[0..])
+                .chain_update(&t.serialize()[0..]);
+            let s = x.mul_point(ctx, &self.s)?;
+            let mut k = Zeroizing::new([0u8; 32]);
+            let mut key = h.chain_update(&s.serialize()[0..]).finalize();
+            k.clone_from_slice(key.as_slice());
+            key.as_mut_slice().zeroize();
+            // Send Over R = t
+            Ok((sent_r { k, choice }, vec![Msg::R(t)]))
+        }
+    }
 
-                let mut ret = got_ciphertext(Vec::new());
-                std::mem::swap(&mut ret.0, &mut results[choice as usize]);
-                ret
+    impl sent_r {
+        /// step takes the ciphertexts and decrypts the one we chose. This
+        /// ends the OT.
+        pub fn step<M, D>(self, incoming: Msg, decrypt: D) -> Result<M>
+        where
+            D: Fn(&[u8], &[u8]) -> M,
+        {
+            let es = match incoming {
+                Msg::Ciphertexts(es) => es,
+                _ => return Err(Error::ProtocolAbort("OT receiver expected ciphertexts")),
+            };
+            if !es.len().is_multiple_of(32) {
+                return Err(Error::ProtocolAbort("OT ciphertexts are 32 bytes each"));
             }
+            let (e, found) = select(&es[..], self.choice as usize);
+            if !found {
+                return Err(Error::ProtocolAbort("too few OT ciphertexts"));
+            }
+            Ok(decrypt(&self.k[..], &e[..]))
+        }
+    }
+}
+
+/// select copies out the choice-th 32 byte ciphertext, reading every
+/// ciphertext and doing the same work whatever choice is. found is false if
+/// there are too few ciphertexts to have one at choice.
+pub(crate) fn select(es: &[u8], choice: usize) -> ([u8; 32], bool) {
+    let mut e = [0u8; 32];
+    let mut found = 0u8;
+    for (i, ei) in es.chunks_exact(32).enumerate() {
+        // d is 0 exactly at choice, so mask is 0xff there and 0 elsewhere
+        let d = (i ^ choice) as u64;
+        let mask = std::hint::black_box((((d | d.wrapping_neg()) >> 63) as u8).wrapping_sub(1));
+        for (w, b) in e.iter_mut().zip(ei.iter()) {
+            *w |= b & mask;
         }
+        found |= mask;
     }
+    (e, found != 0)
 }
 
+/// run is run_async driven to completion on the calling thread.
 pub fn run<T, M, D>(
     ctx: &secp256k1::Secp256k1<T>,
     choice: u8,
+    n: usize,
     decrypt: D,
-    peer: &mut dyn ReadWrite,
-) -> M
+    peer: &mut dyn Channel,
+) -> Result<M>
 where
     T: secp256k1::Verification + secp256k1::Signing,
     D: Fn(&[u8], &[u8]) -> M,
 {
-    let protocol = protocol::start::start::new(peer);
-    let x = generate_key(ctx);
-    let mut started = protocol.next();
-    let (mut s, mut got_s) = protocol::get_s::get_s(&mut started).next(ctx);
-    // check s != G
-    assert_ne!(PublicKey::from_secret_key(ctx, &ONE_KEY), s);
-
-    // compute H(S)
-    let mut t = oracle(ctx, &s);
-    let mut choice_key = [0u8; 32];
-    choice_key[31] = choice;
-    //TODO: Non Constnant time hell
-    match SecretKey::from_slice(ctx, &choice_key) {
-        Ok(c) => {
-            t.mul_assign(ctx, &c);
-            t.add_exp_assign(ctx, &x);
-        }
-        Err(k) => {
-            t = PublicKey::from_secret_key(ctx, &x);
-        }
-    };
-    // Send Over R = t
-    let mut sent_r = protocol::send_r::send_r(&mut got_s).next(&t);
+    futures::executor::block_on(run_async(ctx, choice, n, decrypt, peer))
+}
 
-    let mut h = Sha256::new()
-        .chain(&s.serialize()[0..])
-        .chain(&t.serialize()[0..]);
-    s.mul_assign(ctx, &x);
-    let k = h.chain(&s.serialize()[0..]).result();
+/// run_async picks message `choice` of the n the sender offers.
+pub async fn run_async<T, M, D>(
+    ctx: &secp256k1::Secp256k1<T>,
+    choice: u8,
+    n: usize,
+    decrypt: D,
+    peer: &mut dyn Channel,
+) -> Result<M>
+where
+    T: secp256k1::Verification + secp256k1::Signing,
+    D: Fn(&[u8], &[u8]) -> M,
+{
+    let started = protocol::start::new(choice);
+    let s = super::recv_msg(peer, Kind::OtS, n).await?;
+    let (sent_r, out) = started.step(ctx, s)?;
+    super::send_msgs(peer, out).await?;
 
     // recv msg
-    decrypt(
-        k.as_slice(),
-        &protocol::get_es::get_es(&mut sent_r).next(ctx, choice).0,
-    )
+    let es = super::recv_msg(peer, Kind::OtCiphertexts, n).await?;
+    sent_r.step(es, decrypt)
 }
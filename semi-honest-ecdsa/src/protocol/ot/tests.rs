@@ -1,4 +1,4 @@
-use super::*;
+#![allow(non_snake_case)]
 use crate::protocol;
 #[test]
 fn OT() {
@@ -7,7 +7,9 @@ fn OT() {
     use std::os::unix::net::UnixStream;
     use std::thread;
 
-    let (mut sock1, mut sock2) = UnixStream::pair().unwrap();
+    let (sock1, sock2) = UnixStream::pair().unwrap();
+    let mut sock1 = crate::wire::Framed::new(sock1, 0);
+    let mut sock2 = crate::wire::Framed::new(sock2, 0);
     let h2 = {
         let ctx = ctx.clone();
         thread::spawn(move || {
@@ -15,13 +17,14 @@ fn OT() {
             for (i, m) in v.iter_mut().enumerate() {
                 m[31] = i as u8;
             }
-            for x in 0..=255 {
+            for _ in 0..=255 {
                 protocol::ot::sender::run(
                     &ctx,
-                    &mut v.clone().as_slice(),
+                    &v[..],
                     crate::util::xor_cipher,
                     &mut sock2,
-                );
+                )
+                .unwrap();
             }
         })
     };
@@ -33,11 +36,13 @@ fn OT() {
                 let v = protocol::ot::receiver::run(
                     &ctx,
                     x,
+                    256,
                     crate::util::xor_decipher_scalar,
                     &mut sock1,
-                );
+                )
+                .unwrap();
                 v_orig[31] = x;
-                assert_eq!(v, crate::scalars::secp256k1_scalar_set_b32(&v_orig));
+                assert_eq!(v, crate::scalars::Scalar::from_bytes(&v_orig));
             }
         })
     };
@@ -45,3 +50,175 @@ fn OT() {
     h1.join().unwrap();
     h2.join().unwrap();
 }
+
+#[test]
+fn OT_state_machines() {
+    use protocol::ot::{receiver, sender, Msg};
+    let ctx = &secp256k1::Secp256k1::new();
+    // Carry every message through its wire encoding, as a recorded
+    // transcript would.
+    let replay = |msgs: Vec<Msg>| -> Msg {
+        assert_eq!(msgs.len(), 1);
+        let kind = msgs[0].kind();
+        Msg::from_bytes(kind, &msgs[0].to_bytes()[..]).unwrap()
+    };
+    let mut v = vec![[0u8; 32]; 256];
+    for (i, m) in v.iter_mut().enumerate() {
+        m[31] = i as u8;
+    }
+    for x in [0u8, 1, 128, 255].iter() {
+        let (sent_s, out) = sender::protocol::start::new().step(ctx).unwrap();
+        let (sent_r, out) = receiver::protocol::start::new(*x)
+            .step(ctx, replay(out))
+            .unwrap();
+        let out = sent_s
+            .step(ctx, replay(out), &v[..], crate::util::xor_cipher)
+            .unwrap();
+        let got = sent_r
+            .step(replay(out), crate::util::xor_decipher_scalar)
+            .unwrap();
+        let mut v_orig = [0u8; 32];
+        v_orig[31] = *x;
+        assert_eq!(got, crate::scalars::Scalar::from_bytes(&v_orig));
+    }
+}
+
+#[test]
+fn OT_state_machines_reject_out_of_order() {
+    use protocol::ot::{receiver, sender};
+    let ctx = &secp256k1::Secp256k1::new();
+    let (sent_s, s) = sender::protocol::start::new().step(ctx).unwrap();
+    // the sender never takes its own S back in place of R
+    let v = [[0u8; 32]; 2];
+    assert!(sent_s
+        .step(ctx, s[0].clone(), &v[..], crate::util::xor_cipher)
+        .is_err());
+    let (_, s) = sender::protocol::start::new().step(ctx).unwrap();
+    let (sent_r, r) = receiver::protocol::start::new(0)
+        .step(ctx, s[0].clone())
+        .unwrap();
+    assert!(sent_r
+        .step(r[0].clone(), crate::util::xor_decipher_scalar)
+        .is_err());
+}
+
+#[test]
+fn select_ciphertexts() {
+    use protocol::ot::receiver::select;
+    let es: Vec<u8> = (0..=255u8).flat_map(|i| vec![i; 32]).collect();
+    for choice in 0..=255u8 {
+        assert_eq!(select(&es[..], choice as usize), ([choice; 32], true));
+    }
+    assert_eq!(select(&es[..64], 2), ([0; 32], false));
+}
+
+#[test]
+fn one_of_n() {
+    use protocol::ot::one_of_n;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    assert_eq!(
+        [1, 2, 3, 4, 5, 256, 257].map(one_of_n::bits),
+        [0, 1, 2, 2, 3, 8, 9]
+    );
+    for n in [1usize, 2, 5, 256, 300].iter().copied() {
+        let v: Vec<crate::scalars::Scalar> =
+            (0..n).map(|_| crate::scalars::Scalar::random()).collect();
+        let choices = [0, n / 2, n - 1];
+        let (sock1, sock2) = UnixStream::pair().unwrap();
+        let h = {
+            let v = v.clone();
+            thread::spawn(move || {
+                let ctx = &secp256k1::Secp256k1::new();
+                let mut peer = crate::wire::Framed::new(sock2, 0);
+                for _ in choices.iter() {
+                    one_of_n::send(ctx, &v[..], crate::util::xor_cipher, &mut peer).unwrap();
+                }
+            })
+        };
+        let ctx = &secp256k1::Secp256k1::new();
+        let mut peer = crate::wire::Framed::new(sock1, 0);
+        for choice in choices.iter().copied() {
+            let got = one_of_n::receive(
+                ctx,
+                choice,
+                n,
+                crate::util::xor_decipher_scalar,
+                &mut peer,
+            )
+            .unwrap();
+            assert_eq!(got, v[choice]);
+        }
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn extension() {
+    use protocol::ot::extension::{ReceiverPool, SenderPool, BATCH};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    let (sock1, sock2) = UnixStream::pair().unwrap();
+    // more than a batch, and not a whole number of bytes
+    let choices: Vec<u8> = (0..BATCH + 13).map(|_| rand::random::<u8>() & 1).collect();
+    let h = {
+        let choices = choices.clone();
+        thread::spawn(move || {
+            let ctx = &secp256k1::Secp256k1::new();
+            let mut peer = crate::wire::Framed::new(sock2, 0);
+            let mut pool = ReceiverPool::setup(ctx, &mut peer).unwrap();
+            let first = pool.extend(&choices[..], &mut peer).unwrap();
+            let second = pool.extend(&choices[..7], &mut peer).unwrap();
+            (first, second)
+        })
+    };
+    let ctx = &secp256k1::Secp256k1::new();
+    let mut peer = crate::wire::Framed::new(sock1, 0);
+    let mut pool = SenderPool::setup(ctx, &mut peer).unwrap();
+    let first = pool.extend(choices.len(), &mut peer).unwrap();
+    let second = pool.extend(7, &mut peer).unwrap();
+    let (got_first, got_second) = h.join().unwrap();
+    for (pairs, keys) in [(&first, &got_first), (&second, &got_second)].iter() {
+        assert_eq!(pairs.len(), keys.len());
+        for ((pair, key), c) in pairs.iter().zip(keys.iter()).zip(choices.iter()) {
+            assert_eq!(pair[*c as usize], *key);
+            assert_ne!(pair[1 - *c as usize], *key);
+        }
+    }
+    // each batch draws fresh OTs
+    assert_ne!(first[0], second[0]);
+}
+
+#[test]
+fn OT_batch_shares_one_s() {
+    use protocol::ot::receiver::protocol::got_s;
+    use protocol::ot::sender;
+    let ctx = &secp256k1::Secp256k1::new();
+    let v: Vec<[u8; 32]> = (0..=255u8).map(|i| [i; 32]).collect();
+    let (sent_s, s) = sender::protocol::start::new().step(ctx).unwrap();
+    let got_s = got_s::new(ctx, s[0].clone()).unwrap();
+    let choices = [3u8, 0, 255, 3];
+    let chosen: Vec<_> = choices
+        .iter()
+        .enumerate()
+        .map(|(i, c)| got_s.choose(ctx, i as u32, *c).unwrap())
+        .collect();
+    for (i, (sent_r, r)) in chosen.into_iter().enumerate() {
+        let es = sent_s
+            .step_indexed(ctx, i as u32, r[0].clone(), &v[..], crate::util::xor_cipher)
+            .unwrap();
+        let got = sent_r
+            .step(es[0].clone(), crate::util::xor_decipher_bytes)
+            .unwrap();
+        assert_eq!(got, [choices[i]; 32]);
+    }
+    // an R answered under another index decrypts to nothing useful
+    let (sent_r, r) = got_s.choose(ctx, 1, 7).unwrap();
+    let es = sent_s
+        .step_indexed(ctx, 2, r[0].clone(), &v[..], crate::util::xor_cipher)
+        .unwrap();
+    let got = sent_r
+        .step(es[0].clone(), crate::util::xor_decipher_bytes)
+        .unwrap();
+    assert_ne!(got, [7; 32]);
+}
@@ -1,3 +1,6 @@
+// Most of this follows libsecp256k1's scalar_4x64_impl.h line by line, C
+// names and all, so it can be checked against the original.
+
 /* Limbs of the secp256k1 order. */
 const SECP256K1_N_0: u64 = 0xBFD25E8CD0364141u64;
 const SECP256K1_N_1: u64 = 0xBAAEDCE6AF48A03Bu64;
@@ -9,14 +12,13 @@ const SECP256K1_N_C_0: u64 = (!SECP256K1_N_0) + 1u64;
 const SECP256K1_N_C_1: u64 = !SECP256K1_N_1;
 const SECP256K1_N_C_2: u64 = 1u64;
 
-/* Limbs of half the secp256k1 order. */
-const SECP256K1_N_H_0: u64 = 0xDFE92F46681B20A0u64;
-const SECP256K1_N_H_1: u64 = 0x5D576E7357A4501Du64;
-const SECP256K1_N_H_2: u64 = 0xFFFFFFFFFFFFFFFFu64;
-const SECP256K1_N_H_3: u64 = 0x7FFFFFFFFFFFFFFFu64;
+/// ct_zero_mask is all ones if x is 0 and 0 otherwise, without branching.
+fn ct_zero_mask(x: u64) -> u64 {
+    ((x | x.wrapping_neg()) >> 63).wrapping_sub(1)
+}
 
 pub fn secp256k1_scalar_negate(r: &mut scalar) {
-    let nonzero = (!0u64) * ((*r != [0u64; 4]) as u64);
+    let nonzero = !ct_zero_mask(r[0] | r[1] | r[2] | r[3]);
     let mut overflow = true;
     for (i, v) in [SECP256K1_N_0, SECP256K1_N_1, SECP256K1_N_2, SECP256K1_N_3]
         .iter()
@@ -29,6 +31,7 @@ pub fn secp256k1_scalar_negate(r: &mut scalar) {
     }
 }
 
+#[allow(non_camel_case_types)]
 pub type scalar = [u64; 4];
 pub fn bytes_from_scalar(r: &scalar) -> [u8; 32] {
     let mut bin = [0u8; 32];
@@ -68,6 +71,15 @@ pub fn bytes_from_scalar(r: &scalar) -> [u8; 32] {
 }
 
 pub fn secp256k1_scalar_set_b32(b32: &[u8; 32]) -> scalar {
+    let mut r = limbs_from_b32(b32);
+    let ov = secp256k1_scalar_check_overflow(&r);
+    secp256k1_scalar_reduce(&mut r, ov);
+    r
+}
+
+/// limbs_from_b32 reads a big endian integer without reducing it.
+#[allow(clippy::needless_range_loop)]
+fn limbs_from_b32(b32: &[u8; 32]) -> scalar {
     let mut r = [0u64; 4];
     let mut i = 0;
     for x in 0..=3 {
@@ -81,8 +93,6 @@ pub fn secp256k1_scalar_set_b32(b32: &[u8; 32]) -> scalar {
             | (b32[24 - i] as u64) << 56;
         i += 8;
     }
-    let ov = secp256k1_scalar_check_overflow(&r);
-    secp256k1_scalar_reduce(&mut r, ov);
     r
 }
 
@@ -101,6 +111,7 @@ pub fn secp256k1_scalar_reduce(r: &mut scalar, overflow: bool) {
     }
     r[3] = r[3].wrapping_add(overflow as u64);
 }
+#[cfg(test)]
 pub fn secp256k1_scalar_add(r: &mut scalar, a: &scalar, b: &scalar) -> bool {
     let mut overflow = false;
     for i in 0..=3 {
@@ -109,9 +120,9 @@ pub fn secp256k1_scalar_add(r: &mut scalar, a: &scalar, b: &scalar) -> bool {
         r[i] = t2;
         overflow = overflowed | overflowed2;
     }
-    overflow |= secp256k1_scalar_check_overflow(&r);
+    overflow |= secp256k1_scalar_check_overflow(r);
     secp256k1_scalar_reduce(r, overflow);
-    return overflow;
+    overflow
 }
 
 pub fn secp256k1_scalar_add_assign(r: &mut scalar, a: &scalar) -> bool {
@@ -122,11 +133,13 @@ pub fn secp256k1_scalar_add_assign(r: &mut scalar, a: &scalar) -> bool {
         r[i] = t2;
         overflow = overflowed | overflowed2;
     }
-    overflow |= secp256k1_scalar_check_overflow(&r);
+    overflow |= secp256k1_scalar_check_overflow(r);
     secp256k1_scalar_reduce(r, overflow);
-    return overflow;
+    overflow
 }
 
+#[cfg(test)]
+#[allow(clippy::needless_range_loop)]
 pub fn secp256k1_scalar_double(a: &mut scalar) -> bool {
     let mut overflow = false;
     for i in 0..=3 {
@@ -137,59 +150,286 @@ pub fn secp256k1_scalar_double(a: &mut scalar) -> bool {
     }
     overflow |= secp256k1_scalar_check_overflow(a);
     secp256k1_scalar_reduce(a, overflow);
-    return overflow;
+    overflow
 }
 
+/// secp256k1_scalar_check_overflow is a >= n, taken from the borrow out of
+/// a - n rather than from limb comparisons the compiler may branch on.
 pub fn secp256k1_scalar_check_overflow(a: &scalar) -> bool {
-    let mut yes = false;
-    let mut no = false;
-    no |= a[3] < SECP256K1_N_3; /* No need for a > check. */
-    no |= a[2] < SECP256K1_N_2;
-    yes |= (a[2] > SECP256K1_N_2) & !no;
-    no |= a[1] < SECP256K1_N_1;
-    yes |= (a[1] > SECP256K1_N_1) & !no;
-    yes |= (a[0] >= SECP256K1_N_0) & !no;
-    return yes;
-}
-
-pub fn non_constant_time_shift(a: &mut scalar, bytes: u8) {
-    assert!(bytes <= 31);
-    for i in 0..bytes * 8 {
-        secp256k1_scalar_double(a);
+    let mut borrow = false;
+    for (i, v) in [SECP256K1_N_0, SECP256K1_N_1, SECP256K1_N_2, SECP256K1_N_3]
+        .iter()
+        .enumerate()
+    {
+        let (t, b1) = a[i].overflowing_sub(*v);
+        let (_, b2) = t.overflowing_sub(borrow as u64);
+        borrow = b1 | b2;
     }
+    !borrow
+}
+
+/// shift multiplies a by 256^bytes in constant time.
+pub fn shift(a: &mut Scalar, bytes: u8) {
+    assert!(bytes <= 31);
+    *a = a.mul_pow2(bytes as u32 * 8);
+}
+
+// multiples and assign_add do the same additions whatever their inputs, and
+// additions reduce without branching, so both are constant time.
+
+pub fn scalar_mul_by_256(s: &Scalar) -> Table {
+    multiples(s, 256)
 }
 
-pub fn scalar_mul_by_256(s: &scalar) -> [scalar; 256] {
-    let mut x: [scalar; 256] = [[0; 4]; 256];
-    for i in 1..=255 {
-        x[i] = x[i - 1];
-        secp256k1_scalar_add_assign(&mut x[i], s);
+/// multiples is the table of 0, s, ..., (n-1)s, zero past n.
+pub fn multiples(s: &Scalar, n: usize) -> Table {
+    assert!(n <= 256);
+    let mut x = Table::default();
+    for i in 1..n {
+        x[i] = x[i - 1] + *s;
     }
     x
 }
 
 use rand::prelude::*;
-pub fn random_scalar() -> scalar {
-    random()
-}
 
-pub fn assign_add(s: &mut [scalar; 256], c: &scalar) {
+pub fn assign_add(s: &mut [Scalar], c: &Scalar) {
     for a in s.iter_mut() {
-        secp256k1_scalar_add_assign(a, c);
+        *a += *c;
+    }
+}
+
+/// Table is the 256 multiples of a secret scalar a multiplication sends
+/// through one OT. It lives on the heap, so moving it copies no secrets,
+/// and is wiped when dropped.
+pub struct Table(Box<[Scalar; 256]>);
+
+impl Default for Table {
+    fn default() -> Table {
+        Table(Box::new([Scalar::ZERO; 256]))
+    }
+}
+
+impl std::ops::Deref for Table {
+    type Target = [Scalar; 256];
+    fn deref(&self) -> &[Scalar; 256] {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for Table {
+    fn deref_mut(&mut self) -> &mut [Scalar; 256] {
+        &mut self.0
+    }
+}
+
+impl zeroize::Zeroize for Table {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+/// Scalar is an integer mod the group order n, which every protocol value
+/// (keys, nonces, shares and messages) is kept as.
+///
+/// A Scalar is always fully reduced, so two are equal exactly when they are
+/// equal mod n. Its Debug output never shows the value, as most Scalars
+/// are secret.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Scalar(scalar);
+
+impl Scalar {
+    pub const ZERO: Scalar = Scalar([0; 4]);
+    pub const ONE: Scalar = Scalar([1, 0, 0, 0]);
+
+    /// random draws a Scalar uniformly.
+    pub fn random() -> Scalar {
+        loop {
+            let mut b: [u8; 32] = random();
+            let s = Scalar::from_canonical_bytes(&b);
+            zeroize::Zeroize::zeroize(&mut b);
+            if let Some(s) = s {
+                return s;
+            }
+        }
+    }
+
+    pub fn from_u64(v: u64) -> Scalar {
+        Scalar([v, 0, 0, 0])
+    }
+
+    /// from_bytes reads a big endian integer, reducing it mod n.
+    pub fn from_bytes(b32: &[u8; 32]) -> Scalar {
+        Scalar(secp256k1_scalar_set_b32(b32))
+    }
+
+    /// from_canonical_bytes reads a big endian integer, which must be less
+    /// than n.
+    pub fn from_canonical_bytes(b32: &[u8; 32]) -> Option<Scalar> {
+        let r = limbs_from_b32(b32);
+        if secp256k1_scalar_check_overflow(&r) {
+            None
+        } else {
+            Some(Scalar(r))
+        }
+    }
+
+    /// to_bytes writes the Scalar as a 32 byte big endian integer.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        bytes_from_scalar(&self.0)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    /// pow2 is 2^bits, built without branching on bits.
+    pub fn pow2(bits: u32) -> Scalar {
+        assert!(bits < 256);
+        let mut r = [0u64; 4];
+        for (i, w) in r.iter_mut().enumerate() {
+            *w = (1u64 << (bits % 64)) & ct_zero_mask((bits / 64) as u64 ^ i as u64);
+        }
+        Scalar(r)
+    }
+
+    /// mul_pow2 is x 2^bits, in time independent of both x and bits.
+    pub fn mul_pow2(&self, bits: u32) -> Scalar {
+        *self * Scalar::pow2(bits)
+    }
+
+    /// inv is the multiplicative inverse, taking 0 to 0.
+    pub fn inv(&self) -> Scalar {
+        Scalar(secp256k1_scalar_inverse(&self.0))
+    }
+
+    /// secret_key is the Scalar as a secp256k1 key, which fails for 0.
+    pub fn secret_key(&self) -> Result<secp256k1::SecretKey, secp256k1::Error> {
+        secp256k1::SecretKey::from_slice(&self.to_bytes()[..])
+    }
+
+    /// tweak is the Scalar as a secp256k1 tweak, for multiplying points.
+    pub fn tweak(&self) -> secp256k1::Scalar {
+        match secp256k1::Scalar::from_be_bytes(self.to_bytes()) {
+            Ok(t) => t,
+            Err(_) => unreachable!("a Scalar is always less than n"),
+        }
+    }
+
+    /// mul_point is xP.
+    pub fn mul_point<C: secp256k1::Verification>(
+        &self,
+        ctx: &secp256k1::Secp256k1<C>,
+        p: &secp256k1::PublicKey,
+    ) -> Result<secp256k1::PublicKey, secp256k1::Error> {
+        p.mul_tweak(ctx, &self.tweak())
+    }
+
+    /// base_point is xG, which fails for 0.
+    pub fn base_point<C: secp256k1::Signing>(
+        &self,
+        ctx: &secp256k1::Secp256k1<C>,
+    ) -> Result<secp256k1::PublicKey, secp256k1::Error> {
+        Ok(secp256k1::PublicKey::from_secret_key(ctx, &self.secret_key()?))
+    }
+}
+
+impl From<secp256k1::SecretKey> for Scalar {
+    fn from(k: secp256k1::SecretKey) -> Scalar {
+        Scalar::from_bytes(&k.secret_bytes())
+    }
+}
+
+impl std::ops::Add for Scalar {
+    type Output = Scalar;
+    fn add(mut self, rhs: Scalar) -> Scalar {
+        self += rhs;
+        self
+    }
+}
+
+impl std::ops::AddAssign for Scalar {
+    fn add_assign(&mut self, rhs: Scalar) {
+        secp256k1_scalar_add_assign(&mut self.0, &rhs.0);
+    }
+}
+
+impl std::ops::Sub for Scalar {
+    type Output = Scalar;
+    fn sub(self, rhs: Scalar) -> Scalar {
+        self + -rhs
+    }
+}
+
+impl std::ops::SubAssign for Scalar {
+    fn sub_assign(&mut self, rhs: Scalar) {
+        *self += -rhs;
+    }
+}
+
+impl std::ops::Mul for Scalar {
+    type Output = Scalar;
+    fn mul(self, rhs: Scalar) -> Scalar {
+        Scalar(secp256k1_scalar_mul(&self.0, &rhs.0))
+    }
+}
+
+impl std::ops::MulAssign for Scalar {
+    fn mul_assign(&mut self, rhs: Scalar) {
+        *self = *self * rhs;
+    }
+}
+
+impl std::ops::Neg for Scalar {
+    type Output = Scalar;
+    fn neg(mut self) -> Scalar {
+        secp256k1_scalar_negate(&mut self.0);
+        self
+    }
+}
+
+impl std::iter::Sum for Scalar {
+    fn sum<I: Iterator<Item = Scalar>>(iter: I) -> Scalar {
+        iter.fold(Scalar::ZERO, |acc, x| acc + x)
+    }
+}
+
+impl<'a> std::iter::Sum<&'a Scalar> for Scalar {
+    fn sum<I: Iterator<Item = &'a Scalar>>(iter: I) -> Scalar {
+        iter.fold(Scalar::ZERO, |acc, x| acc + *x)
+    }
+}
+
+impl zeroize::Zeroize for Scalar {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for Scalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Scalar(..)")
     }
 }
 
 pub fn muladd2(a: u64, b: u64, c: &mut (u64, u64, u64)) {
-    let mut t: u128 = (a as u128) * b as u128;
-    let mut th: u64 = (t >> 64) as u64; /* at most 0xFFFFFFFFFFFFFFFE */
+    let t: u128 = (a as u128) * b as u128;
+    let th: u64 = (t >> 64) as u64; /* at most 0xFFFFFFFFFFFFFFFE */
 
-    let mut tl = t as u64;
+    let tl = t as u64;
     let (mut th2, ov1) = th.overflowing_add(th); /* at most 0xFFFFFFFFFFFFFFFE (in case th was 0x7FFFFFFFFFFFFFFF) */
 
     c.2 += ov1 as u64; /* never overflows by contract (verified the next line) */
 
     debug_assert!((th2 >= th) || (c.2 != 0));
-    let (mut tl2, ov2) = tl.overflowing_add(tl); /* at most 0xFFFFFFFFFFFFFFFE (in case the lowest 63 bits of tl were 0x7FFFFFFFFFFFFFFF) */
+    let (tl2, ov2) = tl.overflowing_add(tl); /* at most 0xFFFFFFFFFFFFFFFE (in case the lowest 63 bits of tl were 0x7FFFFFFFFFFFFFFF) */
 
     th2 += ov2 as u64; /* at most 0xFFFFFFFFFFFFFFFF */
 
@@ -335,6 +575,7 @@ fn sumadd_fast(a: u64, c: &mut (u64, u64, u64)) {
     debug_assert!(c.2 == 0);
 }
 
+#[allow(unused_assignments)]
 pub fn secp256k1_scalar_reduce_512(l: &[u64; 8]) -> scalar {
     let mut p0 = 0;
     let mut p1 = 0;
@@ -421,172 +662,10 @@ pub fn secp256k1_scalar_reduce_512(l: &[u64; 8]) -> scalar {
         /* Final reduction of r. */
         let overflow = (c > 0) | secp256k1_scalar_check_overflow(&r);
         secp256k1_scalar_reduce(&mut r, overflow);
-        return r;
+        r
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rand::prelude::*;
-    use secp256k1::key::*;
-    #[test]
-    fn scalar_add_basic() {
-        let a = [0, 1, 2, 3];
-        let b = [4, 5, 6, 7];
-        let c = [4, 6, 8, 10];
-        let mut r = [0u64; 4];
-        secp256k1_scalar_add(&mut r, &a, &b);
-        assert_eq!(c, r);
-    }
-
-    #[test]
-    fn scalar_add_overflows_byte_bound_0() {
-        let a = [!0u64, 1, 2, 0u64];
-        let b = [1u64, 5, 6, 1];
-        let c = [0u64, 7, 8, 1];
-        let mut r = [0u64; 4];
-        let overflowed = secp256k1_scalar_add(&mut r, &a, &b);
-        assert!(!overflowed);
-        assert_eq!(c, r);
-    }
-
-    #[test]
-    fn scalar_add_overflows_byte_bound_1() {
-        let a = [0, !0u64, 1, 2];
-        let b = [1, 1u64, 5, 6];
-        let c = [1, 0, 7, 8];
-        let mut r = [0u64; 4];
-        let overflowed = secp256k1_scalar_add(&mut r, &a, &b);
-        assert!(!overflowed);
-        assert_eq!(c, r);
-    }
-
-    #[test]
-    fn scalar_add_overflows_byte_bound_2() {
-        let a = [2, 0, !0u64, 1];
-        let b = [6, 1, 1u64, 5];
-        let c = [8, 1, 0, 7];
-        let mut r = [0u64; 4];
-        let overflowed = secp256k1_scalar_add(&mut r, &a, &b);
-        assert!(!overflowed);
-        assert_eq!(c, r);
-    }
-
-    #[test]
-    fn scalar_add_negate_zero_random() {
-        let a = random_scalar();
-        let mut b = a.clone();
-        secp256k1_scalar_negate(&mut b);
-        secp256k1_scalar_add_assign(&mut b, &a);
-
-        let r = [0u64; 4];
-        assert_eq!(b, r);
-    }
-    #[test]
-    fn scalar_add_negate_ones() {
-        let a = [1; 4];
-        let mut b = a.clone();
-        secp256k1_scalar_negate(&mut b);
-        secp256k1_scalar_add_assign(&mut b, &a);
-
-        let r = [0u64; 4];
-        assert_eq!(b, r);
-    }
-    #[test]
-    fn scalar_double_flows() {
-        let mut a = [1 << 63, 0, 0, 0];
-        secp256k1_scalar_double(&mut a);
-        assert_eq!(a, [0, 1, 0, 0]);
-    }
-    #[test]
-    fn scalar_non_constant_time_shift() {
-        let mut a = [1, 0, 0, 0];
-        non_constant_time_shift(&mut a, 8);
-        assert_eq!(a, [0, 1, 0, 0]);
-
-        let mut a = [1, 0, 1, 0];
-        non_constant_time_shift(&mut a, 8);
-        assert_eq!(a, [0, 1, 0, 1]);
-    }
-
-    #[test]
-    fn scalar_add_compat() {
-        let ctx = &secp256k1::Secp256k1::new();
-        let mut alpha: [u64; 4] = random();
-        let mut s_alpha = SecretKey::from_slice(ctx, &bytes_from_scalar(&alpha)[..]).unwrap();
-        let mut beta: [u64; 4] = random();
-        let s_beta = SecretKey::from_slice(ctx, &bytes_from_scalar(&beta)[..]).unwrap();
-        let mut r = [0u64; 4];
-        secp256k1_scalar_add(&mut r, &alpha, &beta);
-        let s_r = SecretKey::from_slice(ctx, &bytes_from_scalar(&r)[..]).unwrap();
-
-        s_alpha.add_assign(ctx, &s_beta).unwrap();
-        assert_eq!(s_alpha, s_r);
-    }
-
-    #[test]
-    fn ser_de() {
-        let x = random_scalar();
-        let b32 = bytes_from_scalar(&x);
-        let y = secp256k1_scalar_set_b32(&b32);
-        assert_eq!(x, y);
-    }
-
-    #[test]
-    fn assign_add_correct() {
-        let mut x = random_scalar();
-        let y = random_scalar();
-        let mut xs = [x; 256];
-        assign_add(&mut xs, &y);
-        secp256k1_scalar_add_assign(&mut x, &y);
-        assert_eq!(&xs[..], &[x; 256][..]);
-    }
-    #[test]
-    fn scalar_mul_by_256_correct() {
-        let x = [1, 0, 0, 0];
-        let y = scalar_mul_by_256(&x);
-        let v: Vec<_> = (0..256).map(|i| [i, 0, 0, 0]).collect();
-        assert_eq!(v, &y[..]);
-    }
-    #[test]
-    fn scalar_mul_correct() {
-        let ctx = &secp256k1::Secp256k1::new();
-        let mut alpha: [u64; 4] = random();
-        let mut s_alpha = SecretKey::from_slice(ctx, &bytes_from_scalar(&alpha)[..]).unwrap();
-        let mut beta: [u64; 4] = random();
-        let s_beta = SecretKey::from_slice(ctx, &bytes_from_scalar(&beta)[..]).unwrap();
-        let mut r = secp256k1_scalar_mul(&alpha, &beta);
-        let s_r = SecretKey::from_slice(ctx, &bytes_from_scalar(&r)[..]).unwrap();
-
-        s_alpha.mul_assign(ctx, &s_beta).unwrap();
-        assert_eq!(s_alpha, s_r);
-    }
-
-    #[test]
-    fn scalar_sqr_correct() {
-        let ctx = &secp256k1::Secp256k1::new();
-        let mut alpha: [u64; 4] = random();
-        let mut s_alpha = SecretKey::from_slice(ctx, &bytes_from_scalar(&alpha)[..]).unwrap();
-        let mut r = secp256k1_scalar_sqr(&alpha);
-        let s_r = SecretKey::from_slice(ctx, &bytes_from_scalar(&r)[..]).unwrap();
-
-        let s = s_alpha.clone();
-        s_alpha.mul_assign(ctx, &s).unwrap();
-        assert_eq!(s_alpha, s_r);
-    }
-
-    #[test]
-    fn scalar_inv_correct() {
-        let ctx = &secp256k1::Secp256k1::new();
-        let mut alpha: [u64; 4] = random();
-        let mut r = secp256k1_scalar_inverse(&alpha);
-        let check = secp256k1_scalar_mul(&alpha, &r);
-        assert_eq!(check, [1, 0, 0, 0]);
-    }
-
-}
-
 pub fn secp256k1_scalar_mul(a: &scalar, b: &scalar) -> scalar {
     let l = secp256k1_scalar_mul_512(a, b);
     secp256k1_scalar_reduce_512(&l)
@@ -746,3 +825,209 @@ pub fn secp256k1_scalar_inverse(x: &scalar) -> scalar {
     }
     secp256k1_scalar_mul(t, &x6) /* 111111 */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::SecretKey;
+    #[test]
+    fn scalar_add_basic() {
+        let a = [0, 1, 2, 3];
+        let b = [4, 5, 6, 7];
+        let c = [4, 6, 8, 10];
+        let mut r = [0u64; 4];
+        secp256k1_scalar_add(&mut r, &a, &b);
+        assert_eq!(c, r);
+    }
+
+    #[test]
+    fn scalar_add_overflows_byte_bound_0() {
+        let a = [!0u64, 1, 2, 0u64];
+        let b = [1u64, 5, 6, 1];
+        let c = [0u64, 7, 8, 1];
+        let mut r = [0u64; 4];
+        let overflowed = secp256k1_scalar_add(&mut r, &a, &b);
+        assert!(!overflowed);
+        assert_eq!(c, r);
+    }
+
+    #[test]
+    fn scalar_add_overflows_byte_bound_1() {
+        let a = [0, !0u64, 1, 2];
+        let b = [1, 1u64, 5, 6];
+        let c = [1, 0, 7, 8];
+        let mut r = [0u64; 4];
+        let overflowed = secp256k1_scalar_add(&mut r, &a, &b);
+        assert!(!overflowed);
+        assert_eq!(c, r);
+    }
+
+    #[test]
+    fn scalar_add_overflows_byte_bound_2() {
+        let a = [2, 0, !0u64, 1];
+        let b = [6, 1, 1u64, 5];
+        let c = [8, 1, 0, 7];
+        let mut r = [0u64; 4];
+        let overflowed = secp256k1_scalar_add(&mut r, &a, &b);
+        assert!(!overflowed);
+        assert_eq!(c, r);
+    }
+
+    #[test]
+    fn scalar_add_negate_zero_random() {
+        let a = Scalar::random().0;
+        let mut b = a;
+        secp256k1_scalar_negate(&mut b);
+        secp256k1_scalar_add_assign(&mut b, &a);
+
+        let r = [0u64; 4];
+        assert_eq!(b, r);
+    }
+    #[test]
+    fn scalar_add_negate_ones() {
+        let a = [1; 4];
+        let mut b = a;
+        secp256k1_scalar_negate(&mut b);
+        secp256k1_scalar_add_assign(&mut b, &a);
+
+        let r = [0u64; 4];
+        assert_eq!(b, r);
+    }
+    #[test]
+    fn scalar_double_flows() {
+        let mut a = [1 << 63, 0, 0, 0];
+        secp256k1_scalar_double(&mut a);
+        assert_eq!(a, [0, 1, 0, 0]);
+    }
+    #[test]
+    fn scalar_shift() {
+        let mut a = Scalar([1, 0, 0, 0]);
+        shift(&mut a, 8);
+        assert_eq!(a, Scalar([0, 1, 0, 0]));
+
+        let mut a = Scalar([1, 0, 1, 0]);
+        shift(&mut a, 8);
+        assert_eq!(a, Scalar([0, 1, 0, 1]));
+    }
+
+    #[test]
+    fn mul_pow2_is_doubling() {
+        let x = Scalar::random();
+        let mut doubled = x;
+        for bits in 0..256 {
+            assert_eq!(x.mul_pow2(bits), doubled);
+            secp256k1_scalar_double(&mut doubled.0);
+        }
+    }
+
+    #[test]
+    fn check_overflow_at_n() {
+        let n = [SECP256K1_N_0, SECP256K1_N_1, SECP256K1_N_2, SECP256K1_N_3];
+        assert!(secp256k1_scalar_check_overflow(&n));
+        assert!(secp256k1_scalar_check_overflow(&[!0; 4]));
+        assert!(secp256k1_scalar_check_overflow(&[SECP256K1_N_0 + 1, SECP256K1_N_1, !1, !0]));
+        assert!(!secp256k1_scalar_check_overflow(&[SECP256K1_N_0 - 1, SECP256K1_N_1, !1, !0]));
+        assert!(!secp256k1_scalar_check_overflow(&[!0, !0, !1, SECP256K1_N_3 - 1]));
+        assert!(!secp256k1_scalar_check_overflow(&[0; 4]));
+    }
+
+    #[test]
+    fn scalar_add_compat() {
+        let alpha: [u64; 4] = random();
+        let mut s_alpha = SecretKey::from_slice(&bytes_from_scalar(&alpha)[..]).unwrap();
+        let beta: [u64; 4] = random();
+        let s_beta = SecretKey::from_slice(&bytes_from_scalar(&beta)[..]).unwrap();
+        let mut r = [0u64; 4];
+        secp256k1_scalar_add(&mut r, &alpha, &beta);
+        let s_r = SecretKey::from_slice(&bytes_from_scalar(&r)[..]).unwrap();
+
+        s_alpha = s_alpha.add_tweak(&s_beta.into()).unwrap();
+        assert_eq!(s_alpha, s_r);
+    }
+
+    #[test]
+    fn ser_de() {
+        let x = Scalar::random();
+        assert_eq!(Scalar::from_canonical_bytes(&x.to_bytes()), Some(x));
+        assert_eq!(Scalar::from_bytes(&x.to_bytes()), x);
+        // n itself is not canonical, and reduces to 0
+        let n = bytes_from_scalar(&[SECP256K1_N_0, SECP256K1_N_1, SECP256K1_N_2, SECP256K1_N_3]);
+        assert_eq!(Scalar::from_canonical_bytes(&n), None);
+        assert_eq!(Scalar::from_bytes(&n), Scalar::ZERO);
+    }
+
+    #[test]
+    fn scalar_ops() {
+        let (x, y) = (Scalar::random(), Scalar::random());
+        assert_eq!(x - x, Scalar::ZERO);
+        assert_eq!(x + -y, x - y);
+        assert_eq!(x * x.inv(), Scalar::ONE);
+        assert_eq!(Scalar::ZERO.inv(), Scalar::ZERO);
+        assert_eq!([x, y, x].iter().sum::<Scalar>(), x + x + y);
+        let ctx = secp256k1::Secp256k1::new();
+        let xy_g = x.mul_point(&ctx, &y.base_point(&ctx).unwrap()).unwrap();
+        assert_eq!(xy_g, (x * y).base_point(&ctx).unwrap());
+        assert!(Scalar::ZERO.secret_key().is_err());
+    }
+
+    #[test]
+    fn assign_add_correct() {
+        let mut x = Scalar::random();
+        let y = Scalar::random();
+        let mut xs = [x; 256];
+        assign_add(&mut xs, &y);
+        x += y;
+        assert_eq!(&xs[..], &[x; 256][..]);
+    }
+    #[test]
+    fn scalar_mul_by_256_correct() {
+        let x = Scalar::ONE;
+        let y = scalar_mul_by_256(&x);
+        let v: Vec<_> = (0..256).map(Scalar::from_u64).collect();
+        assert_eq!(v, &y[..]);
+    }
+    #[test]
+    fn table_zeroizes() {
+        let mut t = scalar_mul_by_256(&Scalar::random());
+        assert!(!t[1].is_zero());
+        zeroize::Zeroize::zeroize(&mut t);
+        assert!(t.iter().all(Scalar::is_zero));
+    }
+    #[test]
+    fn debug_redacts() {
+        let s = Scalar::from_u64(0xdeadbeef);
+        assert_eq!(format!("{:?}", s), "Scalar(..)");
+    }
+    #[test]
+    fn scalar_mul_correct() {
+        let alpha: [u64; 4] = random();
+        let mut s_alpha = SecretKey::from_slice(&bytes_from_scalar(&alpha)[..]).unwrap();
+        let beta: [u64; 4] = random();
+        let s_beta = SecretKey::from_slice(&bytes_from_scalar(&beta)[..]).unwrap();
+        let r = secp256k1_scalar_mul(&alpha, &beta);
+        let s_r = SecretKey::from_slice(&bytes_from_scalar(&r)[..]).unwrap();
+
+        s_alpha = s_alpha.mul_tweak(&s_beta.into()).unwrap();
+        assert_eq!(s_alpha, s_r);
+    }
+
+    #[test]
+    fn scalar_sqr_correct() {
+        let alpha: [u64; 4] = random();
+        let mut s_alpha = SecretKey::from_slice(&bytes_from_scalar(&alpha)[..]).unwrap();
+        let r = secp256k1_scalar_sqr(&alpha);
+        let s_r = SecretKey::from_slice(&bytes_from_scalar(&r)[..]).unwrap();
+
+        let s = s_alpha;
+        s_alpha = s_alpha.mul_tweak(&s.into()).unwrap();
+        assert_eq!(s_alpha, s_r);
+    }
+
+    #[test]
+    fn scalar_inv_correct() {
+        let alpha: [u64; 4] = random();
+        let r = secp256k1_scalar_inverse(&alpha);
+        let check = secp256k1_scalar_mul(&alpha, &r);
+        assert_eq!(check, [1, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::error::{Error, Result};
+use crate::util::ReadWrite;
+use futures::future::{self, BoxFuture};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use sha2::{Digest, Sha256};
+#[cfg(test)]
+mod tests;
+
+/// VERSION is sent in every frame header and must match exactly.
+pub const VERSION: u8 = 1;
+
+/// MAX_PAYLOAD bounds what a peer can make us allocate for one frame. The
+/// largest message is one OT's 256 ciphertexts, and batches larger than this
+/// are split over several frames.
+pub const MAX_PAYLOAD: usize = 1 << 16;
+
+/// A frame header is version, kind, session, seq and payload length, the
+/// last three big endian.
+pub const HEADER_LEN: usize = 14;
+
+/// Kind tags every frame with the protocol step that produced it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Kind {
+    /// OT sender's S = yG
+    OtS = 1,
+    /// OT receiver's R = cT + xG
+    OtR = 2,
+    /// OT sender's ciphertexts, 32 bytes each
+    OtCiphertexts = 3,
+    /// a participant's public key beta_i
+    PublicKey = 4,
+    /// a partial or the final nonce point passed on during nonce generation
+    NoncePoint = 5,
+    /// the x coordinate of the shared nonce K G
+    NonceX = 6,
+    /// a participant's additive share of s
+    Share = 7,
+    /// a node asking its peer to join a session
+    Request = 8,
+    /// whether the peer joins the requested session
+    Accept = 9,
+    /// an OT extension receiver's 128 columns u_i
+    OtExtension = 10,
+    /// hashes committing to values revealed in a later step
+    Commitment = 11,
+    /// the values opening earlier commitments, e.g. coins
+    Reveal = 12,
+}
+
+impl Kind {
+    pub fn from_u8(b: u8) -> Option<Kind> {
+        Some(match b {
+            1 => Kind::OtS,
+            2 => Kind::OtR,
+            3 => Kind::OtCiphertexts,
+            4 => Kind::PublicKey,
+            5 => Kind::NoncePoint,
+            6 => Kind::NonceX,
+            7 => Kind::Share,
+            8 => Kind::Request,
+            9 => Kind::Accept,
+            10 => Kind::OtExtension,
+            11 => Kind::Commitment,
+            12 => Kind::Reveal,
+            _ => return None,
+        })
+    }
+}
+
+/// session_id derives a session id both sides can compute from values they
+/// already agree on.
+pub fn session_id(parts: &[&[u8]]) -> u32 {
+    let h = parts
+        .iter()
+        .fold(Sha256::new(), |h, p| h.chain_update(p))
+        .finalize();
+    let mut b = [0u8; 4];
+    b.clone_from_slice(&h.as_slice()[..4]);
+    u32::from_be_bytes(b)
+}
+
+/// Framed carries one protocol run over a stream as a sequence of frames.
+///
+/// Each direction numbers its frames from 0, so both ends of a run must
+/// open their Framed at the same point in the conversation. Any frame with
+/// the wrong version, kind, session, sequence number or length is an error
+/// rather than being read as the next field.
+pub struct Framed<T: ?Sized> {
+    session: u32,
+    sent: u32,
+    received: u32,
+    inner: T,
+}
+
+impl<T> Framed<T> {
+    pub fn new(inner: T, session: u32) -> Framed<T> {
+        Framed {
+            session,
+            sent: 0,
+            received: 0,
+            inner,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: ?Sized> Framed<T> {
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// sub opens a fresh run in the same session over the same stream, for a
+    /// sub-protocol (e.g. a multiplication) the peer opens at the same point.
+    pub fn sub(&mut self) -> Framed<&mut T> {
+        Framed::new(&mut self.inner, self.session)
+    }
+
+    fn header(&self, kind: Kind, len: usize) -> Result<[u8; HEADER_LEN]> {
+        if len > MAX_PAYLOAD {
+            return Err(Error::ProtocolAbort("frame payload too large"));
+        }
+        let mut header = [0u8; HEADER_LEN];
+        header[0] = VERSION;
+        header[1] = kind as u8;
+        header[2..6].clone_from_slice(&self.session.to_be_bytes());
+        header[6..10].clone_from_slice(&self.sent.to_be_bytes());
+        header[10..14].clone_from_slice(&(len as u32).to_be_bytes());
+        Ok(header)
+    }
+
+    fn check(&self, kind: Kind, header: &[u8; HEADER_LEN], len: usize) -> Result<()> {
+        if header[0] != VERSION {
+            return Err(Error::UnsupportedVersion(header[0]));
+        }
+        let field = |i: usize| {
+            let mut b = [0u8; 4];
+            b.clone_from_slice(&header[i..i + 4]);
+            u32::from_be_bytes(b)
+        };
+        let (session, seq, got) = (field(2), field(6), field(10));
+        if header[1] != kind as u8
+            || session != self.session
+            || seq != self.received
+            || got as usize != len
+        {
+            return Err(Error::UnexpectedMessage {
+                kind: header[1],
+                session,
+                seq,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadWrite + ?Sized> Framed<T> {
+    /// send writes payload as the next frame and flushes it.
+    pub fn send(&mut self, kind: Kind, payload: &[u8]) -> Result<()> {
+        let header = self.header(kind, payload.len())?;
+        self.inner.write_all(&header[..])?;
+        self.inner.write_all(payload)?;
+        self.inner.flush()?;
+        self.sent = self.sent.wrapping_add(1);
+        Ok(())
+    }
+
+    /// recv reads the next frame into buf, which must be exactly the
+    /// expected payload length for `kind`.
+    pub fn recv(&mut self, kind: Kind, buf: &mut [u8]) -> Result<()> {
+        let mut header = [0u8; HEADER_LEN];
+        self.inner.read_exact(&mut header[..])?;
+        self.check(kind, &header, buf.len())?;
+        self.inner.read_exact(buf)?;
+        self.received = self.received.wrapping_add(1);
+        Ok(())
+    }
+}
+
+/// AsyncFramed is Framed over an AsyncRead + AsyncWrite stream, speaking the
+/// same frames.
+pub struct AsyncFramed<T>(Framed<T>);
+
+impl<T> AsyncFramed<T> {
+    pub fn new(inner: T, session: u32) -> AsyncFramed<T> {
+        AsyncFramed(Framed::new(inner, session))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+
+    pub fn sub(&mut self) -> AsyncFramed<&mut T> {
+        AsyncFramed(self.0.sub())
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncFramed<T> {
+    pub async fn send(&mut self, kind: Kind, payload: &[u8]) -> Result<()> {
+        let header = self.0.header(kind, payload.len())?;
+        self.0.inner.write_all(&header[..]).await?;
+        self.0.inner.write_all(payload).await?;
+        self.0.inner.flush().await?;
+        self.0.sent = self.0.sent.wrapping_add(1);
+        Ok(())
+    }
+
+    pub async fn recv(&mut self, kind: Kind, buf: &mut [u8]) -> Result<()> {
+        let mut header = [0u8; HEADER_LEN];
+        self.0.inner.read_exact(&mut header[..]).await?;
+        self.0.check(kind, &header, buf.len())?;
+        self.0.inner.read_exact(buf).await?;
+        self.0.received = self.0.received.wrapping_add(1);
+        Ok(())
+    }
+}
+
+/// A Channel is a framed connection the protocols can run over, blocking or
+/// async.
+///
+/// The protocols are written once as async fns over a Channel. A blocking
+/// Framed completes each step before returning its future, so the blocking
+/// API just drives them with block_on.
+pub trait Channel: Send {
+    fn send_frame<'a>(&'a mut self, kind: Kind, payload: &'a [u8]) -> BoxFuture<'a, Result<()>>;
+    fn recv_frame<'a>(
+        &'a mut self,
+        kind: Kind,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<()>>;
+    /// sub is Framed::sub for any Channel.
+    fn sub_channel(&mut self) -> Box<dyn Channel + '_>;
+}
+
+/// AsyncStream is what an AsyncFramed runs over, as a trait object so sub
+/// channels of sub channels all have the same type.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + ?Sized> AsyncStream for T {}
+
+impl<T: ReadWrite> Channel for Framed<T> {
+    fn send_frame<'a>(&'a mut self, kind: Kind, payload: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(future::ready(self.send(kind, payload)))
+    }
+
+    fn recv_frame<'a>(
+        &'a mut self,
+        kind: Kind,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(future::ready(self.recv(kind, buf)))
+    }
+
+    fn sub_channel(&mut self) -> Box<dyn Channel + '_> {
+        let inner: &mut dyn ReadWrite = &mut self.inner;
+        Box::new(Framed::new(inner, self.session))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Channel for AsyncFramed<T> {
+    fn send_frame<'a>(&'a mut self, kind: Kind, payload: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.send(kind, payload))
+    }
+
+    fn recv_frame<'a>(
+        &'a mut self,
+        kind: Kind,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.recv(kind, buf))
+    }
+
+    fn sub_channel(&mut self) -> Box<dyn Channel + '_> {
+        let inner: &mut dyn AsyncStream = &mut self.0.inner;
+        Box::new(AsyncFramed::new(inner, self.0.session))
+    }
+}
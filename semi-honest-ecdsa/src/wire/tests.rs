@@ -0,0 +1,64 @@
+use super::*;
+use std::os::unix::net::UnixStream;
+
+fn pair(session: u32) -> (Framed<UnixStream>, Framed<UnixStream>) {
+    let (a, b) = UnixStream::pair().unwrap();
+    (Framed::new(a, session), Framed::new(b, session))
+}
+
+#[test]
+fn round_trip() {
+    let (mut a, mut b) = pair(7);
+    a.send(Kind::Share, &[1u8; 32]).unwrap();
+    a.send(Kind::Share, &[2u8; 32]).unwrap();
+    let mut buf = [0u8; 32];
+    b.recv(Kind::Share, &mut buf).unwrap();
+    assert_eq!(buf, [1u8; 32]);
+    b.recv(Kind::Share, &mut buf).unwrap();
+    assert_eq!(buf, [2u8; 32]);
+}
+
+#[test]
+fn rejects_wrong_kind() {
+    let (mut a, mut b) = pair(7);
+    a.send(Kind::NonceX, &[0u8; 32]).unwrap();
+    let mut buf = [0u8; 32];
+    match b.recv(Kind::Share, &mut buf) {
+        Err(Error::UnexpectedMessage { kind, session, seq }) => {
+            assert_eq!(kind, Kind::NonceX as u8);
+            assert_eq!(session, 7);
+            assert_eq!(seq, 0);
+        }
+        _ => panic!("accepted a frame of the wrong kind"),
+    }
+}
+
+#[test]
+fn rejects_wrong_session_and_length() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let mut a = Framed::new(a, 1);
+    let mut b = Framed::new(b, 2);
+    a.send(Kind::Share, &[0u8; 32]).unwrap();
+    let mut buf = [0u8; 32];
+    assert!(b.recv(Kind::Share, &mut buf).is_err());
+
+    let (mut a, mut b) = pair(3);
+    a.send(Kind::Share, &[0u8; 31]).unwrap();
+    assert!(b.recv(Kind::Share, &mut buf).is_err());
+}
+
+#[test]
+fn rejects_other_versions() {
+    use std::io::Write;
+    let (mut a, b) = UnixStream::pair().unwrap();
+    let mut b = Framed::new(b, 0);
+    let mut header = [0u8; HEADER_LEN];
+    header[0] = VERSION + 1;
+    header[1] = Kind::Share as u8;
+    a.write_all(&header[..]).unwrap();
+    let mut buf = [0u8; 0];
+    match b.recv(Kind::Share, &mut buf) {
+        Err(Error::UnsupportedVersion(v)) => assert_eq!(v, VERSION + 1),
+        _ => panic!("accepted a frame from another version"),
+    }
+}